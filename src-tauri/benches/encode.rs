@@ -0,0 +1,54 @@
+//! Benchmarks encoding the textures of a pack with a BNTX field texture.
+//!
+//! Run with `cargo bench --bench encode`.
+
+use app_lib::{
+    encoding::{AssetRule, AssetRules, ImageEncoding},
+    ninres::{encode_assets, EncodeProgress},
+    Result,
+};
+use criterion::{criterion_group, criterion_main, Criterion};
+
+/// A SARC with a zstd compressed BFRES, which embeds a 256x768 field texture.
+static FIELD_PACK: &[u8] = include_bytes!("../tests/fixtures/field.pack");
+
+struct NoProgress;
+
+impl EncodeProgress for NoProgress {
+    fn add_images(&self, _count: u64) {}
+
+    fn encoded(&self, _count: u64) -> Result<()> {
+        Ok(())
+    }
+}
+
+fn encode(rules: &AssetRules) -> usize {
+    let entries = encode_assets(FIELD_PACK, rules, 0, &NoProgress).unwrap();
+    assert!(!entries.is_empty(), "no textures in the fixture");
+    entries.len()
+}
+
+fn bench_encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("field_pack");
+    group.sample_size(20);
+
+    let rules = AssetRules::new(&[], false).unwrap();
+    group.bench_function("png", |b| b.iter(|| encode(&rules)));
+    let rules = AssetRules::new(&[], true).unwrap();
+    group.bench_function("png_oxipng", |b| b.iter(|| encode(&rules)));
+    for encoding in [ImageEncoding::WebP, ImageEncoding::Ktx2Rgba8] {
+        let rules = AssetRules::new(
+            &[AssetRule {
+                pattern: "*".to_string(),
+                encoding,
+            }],
+            false,
+        )
+        .unwrap();
+        group.bench_function(encoding.extension(), |b| b.iter(|| encode(&rules)));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_encode);
+criterion_main!(benches);
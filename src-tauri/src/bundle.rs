@@ -1,23 +1,40 @@
-use crate::{error::Error, increase_progress_sync, ninres::bundle_ninres, Result};
-use glob::glob;
-use ninres::NinRes;
-use rayon::iter::{ParallelBridge, ParallelIterator};
+use crate::{
+    cache::{hash_data, Cache},
+    encoding::AssetRules,
+    error::{Error, ResultExt},
+    layers::{Layer, LayeredFs},
+    manifest::{BaseBundle, Manifest, ManifestAsset, MANIFEST_NAME},
+    ninres::encode_assets,
+    progress::{Phase, Progress},
+    run_log::RunLog,
+    tar_writer::{TarEntry, TarSender},
+    Result,
+};
+use glob::{glob, Pattern};
 use std::{
-    fs::{self, read_dir, DirEntry, File},
-    io::Cursor,
+    collections::HashSet,
+    io::{self, Read},
+    panic::{self, AssertUnwindSafe},
     path::{Path, PathBuf},
-    sync::{Arc, Mutex, RwLock},
+    sync::Mutex,
     time::SystemTime,
 };
 use tauri::Window;
-use tempfile::TempDir;
-use zip::ZipArchive;
 
-pub fn find_romfs_dir(dir: &TempDir) -> Result<PathBuf> {
+/// Finds the romfs directory within a directory, e.g. a dumped romfs or a LayeredFS mod.
+///
+/// If the directory directly contains the `Model` or `Pack` directory, it is the romfs itself.
+pub fn find_romfs_dir(dir: &Path) -> Result<PathBuf> {
+    if dir.ends_with("romfs") || dir.join("Model").is_dir() || dir.join("Pack").is_dir() {
+        return Ok(dir.to_path_buf());
+    }
     let mut romfs_dir = None;
-    for entry in glob(&format!("{}/**/*", dir.path().display()))? {
+    for entry in glob(&format!(
+        "{}/**/romfs",
+        Pattern::escape(&dir.to_string_lossy())
+    ))? {
         let entry = entry?;
-        if entry.ends_with("romfs") {
+        if entry.is_dir() {
             romfs_dir = Some(entry);
             break;
         }
@@ -25,115 +42,310 @@ pub fn find_romfs_dir(dir: &TempDir) -> Result<PathBuf> {
     romfs_dir.ok_or_else(|| Error::DirNotFound("romfs".to_string()))
 }
 
-pub fn find_romfs_dir_in_zip_archive(archive: &mut ZipArchive<File>) -> Result<PathBuf> {
-    let mut romfs_dir = None;
-    if archive
-        .by_index(0)
-        .ok()
-        .and_then(|file| file.enclosed_name().map(|name| name.starts_with("romfs")))
-        .unwrap_or_default()
-    {
-        return Ok(PathBuf::from("romfs"));
-    }
-    for i in 0..archive.len() {
-        let file = archive.by_index(i).unwrap();
-        if let Some(name) = file.enclosed_name() {
-            if name.ends_with("romfs") {
-                romfs_dir = Some(name.to_owned());
-                break;
-            }
+/// Lists all files within a directory recursively.
+pub fn list_dir(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for entry in glob(&format!("{}/**/*", Pattern::escape(&dir.to_string_lossy())))? {
+        let entry = entry?;
+        if entry.is_file() {
+            files.push(entry);
         }
     }
-    romfs_dir.ok_or_else(|| Error::DirNotFound("romfs".to_string()))
+    Ok(files)
+}
+
+/// Finds the romfs directory within the entry names of an archive.
+pub fn find_romfs_dir_in_names<'a>(names: impl IntoIterator<Item = &'a Path>) -> Result<PathBuf> {
+    names
+        .into_iter()
+        .find_map(|name| {
+            let mut romfs_dir = PathBuf::new();
+            for component in name.components() {
+                romfs_dir.push(component);
+                if component.as_os_str() == "romfs" {
+                    return Some(romfs_dir);
+                }
+            }
+            None
+        })
+        .ok_or_else(|| Error::DirNotFound("romfs".to_string()))
 }
 
-pub fn bundle_assets(
+/// Bundles the assets of all layers, where each asset is taken from the highest layer
+/// providing it.
+///
+/// Zip archives are read without extracting them to disk.
+///
+/// If a base bundle is given, entries of unchanged assets are copied from it instead of
+/// processing the assets again, and entries of assets, which are not provided by any layer,
+/// are kept. Returns the manifest of the new bundle.
+///
+/// Entries are sent to a tar writer in the order of their assets, so that the bundle does not
+/// depend on the order in which the assets have been processed.
+///
+/// Assets, which cannot be bundled, do not stop the remaining assets, but fail the run
+/// afterwards, so that an incomplete bundle is never reported as finished.
+#[allow(clippy::too_many_arguments)]
+pub fn bundle_layers(
     window: Window,
-    builder: &RwLock<tar::Builder<Cursor<Vec<u8>>>>,
-    dir: &TempDir,
-    romfs_dir: &Path,
-    progress: Arc<RwLock<f64>>,
-    max_progress: u32,
+    log: &RunLog,
+    sender: &TarSender,
+    layered_fs: &LayeredFs,
+    cache: Option<&Cache>,
+    base_bundle: Option<&BaseBundle>,
+    rules: &AssetRules,
+    progress: &Progress,
     file_message: &str,
-) -> Result<()> {
-    window
-        .emit("extract_step", &format!("{}\nBundling...", file_message))
-        .unwrap();
+) -> Result<Manifest> {
+    window.emit("extract_step", &format!("{}\nBundling...", file_message))?;
 
-    let ninres_dir = dir.path().join("ninres");
-    let model_dir = romfs_dir.join("Model");
-    let pack_dir = romfs_dir.join("Pack");
+    progress.add_total(Phase::Bundle, layered_fs.asset_count() as u64);
+    progress.start(Phase::Bundle);
+    progress.start(Phase::Encode);
+    let bundler = Bundler::new(log, sender, cache, base_bundle, rules, progress);
+    // the scope runs on this thread instead of the thread pool, so that waiting for the
+    // writer never blocks a worker
+    rayon::in_place_scope(|s| -> Result<()> {
+        for (sequence, (asset, layer)) in layered_fs.assets().enumerate() {
+            // assets are started in the order in which they are written, so that the writer
+            // holds back a bounded number of records, however slow a single asset is
+            sender.reserve(sequence)?;
+            let bundler = &bundler;
+            s.spawn(move |_| bundler.bundle_asset(sequence, layer, asset));
+        }
+        Ok(())
+    })?;
 
-    let mtime = SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .unwrap()
-        .as_secs();
+    let mut failures = bundler.failures.into_inner().unwrap();
+    if !failures.is_empty() {
+        failures.sort();
+        return Err(Error::AssetsFailed(failures));
+    }
+    bundler.keep_base_entries(layered_fs.asset_count())?;
+    // reused and cached assets are not encoded again, so the phases might not be complete
+    progress.complete(Phase::Bundle)?;
+    progress.complete(Phase::Encode)?;
+    Ok(bundler.manifest.into_inner().unwrap())
+}
 
-    let mut max_completed = 0u32;
-    let completed = Arc::new(RwLock::new(0u32));
-    let start_progress = *progress.read().unwrap();
+struct Bundler<'a> {
+    log: &'a RunLog,
+    sender: &'a TarSender,
+    cache: Option<&'a Cache>,
+    base_bundle: Option<&'a BaseBundle<'a>>,
+    rules: &'a AssetRules,
+    manifest: Mutex<Manifest>,
+    /// Assets, which could not be bundled.
+    failures: Mutex<Vec<String>>,
+    mtime: u64,
+    progress: &'a Progress,
+}
 
-    read_dir(model_dir.clone())
-        .into_iter()
-        .flatten()
-        .for_each(|_| max_completed += 1);
-    read_dir(pack_dir.clone())
-        .into_iter()
-        .flatten()
-        .for_each(|_| max_completed += 1);
+impl<'a> Bundler<'a> {
+    fn new(
+        log: &'a RunLog,
+        sender: &'a TarSender,
+        cache: Option<&'a Cache>,
+        base_bundle: Option<&'a BaseBundle<'a>>,
+        rules: &'a AssetRules,
+        progress: &'a Progress,
+    ) -> Self {
+        let mtime = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        Self {
+            log,
+            sender,
+            cache,
+            base_bundle,
+            rules,
+            manifest: Mutex::new(Manifest::default()),
+            failures: Mutex::new(vec![]),
+            mtime,
+            progress,
+        }
+    }
 
-    let window = Arc::new(Mutex::new(window));
-    read_dir(model_dir)
-        .into_iter()
-        .flatten()
-        .chain(read_dir(pack_dir).into_iter().flatten())
-        .par_bridge()
-        .map(|dir_entry| -> Result<_> {
-            let dir_entry: DirEntry = dir_entry?;
-            let line = format!(
-                "Bundling {:?}",
-                dir_entry.path().file_name().unwrap_or_default()
-            );
-            window.lock().unwrap().emit("extract_message", line)?;
-            let file_data = fs::read(dir_entry.path())?;
-            if let Ok(ninres) = file_data.as_ninres() {
-                bundle_ninres(&ninres, builder, ninres_dir.clone(), mtime)?;
+    /// Sends the entries of an asset, which are empty, if the asset could not be bundled or
+    /// its decoding panicked, so that the writer never waits for the asset.
+    fn bundle_asset(&self, sequence: usize, layer: &Layer, asset: &Path) {
+        let input = &layer.input;
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let file_data = layer.read(asset)?.ok_or_else(|| {
+                Error::from(io::Error::from(io::ErrorKind::NotFound)).with_path(asset)
+            })?;
+            self.bundle_file(input, asset, &file_data)
+        }))
+        .unwrap_or_else(|payload| {
+            let message = payload
+                .downcast_ref::<&str>()
+                .map(|message| message.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_default();
+            let message = format!("bundling panicked: {}", message);
+            Err(io::Error::new(io::ErrorKind::Other, message).into())
+        });
+        let entries = result.unwrap_or_else(|err| {
+            self.record_failure(input, asset, err);
+            vec![]
+        });
+        // a writer, which has stopped, reports its error, when it is finished
+        self.sender.send(sequence, entries).ok();
+    }
+
+    fn bundle_file(&self, input: &Path, asset: &Path, file_data: &[u8]) -> Result<Vec<TarEntry>> {
+        let key = hash_data(file_data, self.rules.fingerprint());
+        let reusable_entries = self.base_bundle.and_then(|base_bundle| {
+            Some((base_bundle, base_bundle.reusable_entries(asset, &key)?))
+        });
+        let (entries, manifest_asset) = match reusable_entries {
+            Some((base_bundle, manifest_asset)) => {
+                let line = format!("Reusing {}", asset.to_string_lossy());
+                self.log.debug(Some(Phase::Bundle), Some(input), line)?;
+                let entries = manifest_asset
+                    .entries
+                    .iter()
+                    .map(|entry| base_bundle.entry(entry))
+                    .collect::<Result<_>>()?;
+                (entries, manifest_asset.clone())
             }
-            let c = *completed.read().unwrap() + 1;
-            *completed.write().unwrap() = c;
-            *progress.write().unwrap() = start_progress + (c as f64 / max_completed as f64) * 2.;
-            let extract_progress = (*progress.read().unwrap() as f64 / max_progress as f64) * 100.;
-            window
-                .lock()
-                .unwrap()
-                .emit("extract_progress", extract_progress)?;
-            Ok(())
-        })
-        .map(Result::ok)
-        .collect::<Vec<_>>();
+            None => {
+                let line = format!("Bundling {}", asset.to_string_lossy());
+                self.log.debug(Some(Phase::Bundle), Some(input), line)?;
+                let entries = match self.cache.and_then(|cache| cache.assets(&key)) {
+                    Some(bundle_data) => read_bundle(&bundle_data)?,
+                    None => {
+                        let entries =
+                            encode_assets(file_data, self.rules, self.mtime, self.progress)?;
+                        if let Some(cache) = self.cache {
+                            cache.store_assets(&key, &write_bundle(&entries)?)?;
+                        }
+                        entries
+                    }
+                };
+                let paths = entries.iter().map(|entry| entry.path.clone()).collect();
+                let manifest_asset = ManifestAsset {
+                    hash: key,
+                    entries: paths,
+                };
+                (entries, manifest_asset)
+            }
+        };
+        self.manifest
+            .lock()
+            .unwrap()
+            .assets
+            .insert(asset.to_path_buf(), manifest_asset);
+        self.progress.advance(Phase::Bundle, 1)?;
+        Ok(entries)
+    }
+
+    /// Logs and records an asset, which could not be bundled, so that the remaining assets are
+    /// still bundled before the run fails.
+    fn record_failure(&self, input: &Path, asset: &Path, err: Error) {
+        let line = format!("Failed to bundle {}: {}", asset.to_string_lossy(), err);
+        self.log.error(Some(Phase::Bundle), Some(input), line).ok();
+        self.failures
+            .lock()
+            .unwrap()
+            .push(asset.to_string_lossy().into_owned());
+    }
 
-    Ok(())
+    /// Copies all entries of the base bundle, which have not been replaced, behind the entries
+    /// of all assets.
+    ///
+    /// Entries of assets, which have been processed again, are dropped, since the assets
+    /// might not produce them anymore.
+    fn keep_base_entries(&self, sequence: usize) -> Result<()> {
+        let base_bundle = match self.base_bundle {
+            Some(base_bundle) => base_bundle,
+            None => return Ok(()),
+        };
+        let mut manifest = self.manifest.lock().unwrap();
+        let mut dropped: HashSet<PathBuf> = manifest
+            .assets
+            .values()
+            .flat_map(|manifest_asset| manifest_asset.entries.iter().cloned())
+            .collect();
+        if let Some(base_manifest) = &base_bundle.manifest {
+            for (asset, manifest_asset) in base_manifest.assets.iter() {
+                if manifest.assets.contains_key(asset) {
+                    dropped.extend(manifest_asset.entries.iter().cloned());
+                } else {
+                    manifest
+                        .assets
+                        .insert(asset.clone(), manifest_asset.clone());
+                }
+            }
+        }
+
+        let mut kept: Vec<_> = base_bundle
+            .paths()
+            .filter(|path| !dropped.contains(*path))
+            .collect();
+        kept.sort();
+        let entries = kept
+            .into_iter()
+            .map(|path| base_bundle.entry(path))
+            .collect::<Result<_>>()?;
+        self.sender.send(sequence, entries)
+    }
+}
+
+/// Writes the entries of an asset file into a separate bundle, so that they can be cached.
+fn write_bundle(entries: &[TarEntry]) -> Result<Vec<u8>> {
+    let mut builder = tar::Builder::new(vec![]);
+    for entry in entries {
+        builder.append_data(&mut entry.header.clone(), &entry.path, &entry.data[..])?;
+    }
+    Ok(builder.into_inner()?)
+}
+
+/// Reads all entries of a cached bundle sorted by their paths, since bundles of previous
+/// versions have been written in the order in which their images have been encoded.
+fn read_bundle(bundle_data: &[u8]) -> Result<Vec<TarEntry>> {
+    let mut entries = vec![];
+    for entry in tar::Archive::new(bundle_data).entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        let header = entry.header().clone();
+        let mut data = Vec::with_capacity(entry.size() as usize);
+        entry.read_to_end(&mut data)?;
+        entries.push(TarEntry { path, header, data });
+    }
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(entries)
 }
 
 pub fn finish_bundle_assets(
     window: Window,
-    builder: RwLock<tar::Builder<Cursor<Vec<u8>>>>,
-    progress: Arc<RwLock<f64>>,
-    max_progress: u32,
+    log: &RunLog,
+    mut builder: tar::Builder<Vec<u8>>,
+    manifest: &Manifest,
+    progress: &Progress,
     file_message: &str,
 ) -> Result<Vec<u8>> {
-    let mut builder = builder.into_inner().unwrap();
-    let window = Arc::new(Mutex::new(window));
+    progress.start(Phase::Finish);
+    let manifest_data = manifest.to_json()?;
+    let mut header = tar::Header::new_gnu();
+    header.set_size(manifest_data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs(),
+    );
+    header.set_cksum();
+    builder.append_data(&mut header, MANIFEST_NAME, &manifest_data[..])?;
 
     builder.finish()?;
-    let data = builder.into_inner()?.into_inner();
-
-    increase_progress_sync(window.clone(), progress, max_progress)?;
-    window
-        .lock()
-        .unwrap()
-        .emit("extract_step", &format!("{}\nFinished!", file_message))?;
-    window.lock().unwrap().emit("extract_message", "Finished")?;
+    let data = builder.into_inner()?;
+
+    progress.complete(Phase::Finish)?;
+    window.emit("extract_step", &format!("{}\nFinished!", file_message))?;
+    log.info(Some(Phase::Finish), None, "Finished")?;
 
     Ok(data)
 }
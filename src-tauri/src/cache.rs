@@ -0,0 +1,267 @@
+use crate::{error::ResultExt, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const CACHE_DIR_NAME: &str = "shroom-kingdom-asset-extractor";
+
+/// Size of the cache, beyond which the least recently used entries are evicted.
+const MAX_CACHE_SIZE: u64 = 16 * 1024 * 1024 * 1024;
+
+/// Number of bytes at the start and at the end of a game dump, which are part of its key.
+const PARTIAL_HASH_SIZE: u64 = 1024 * 1024;
+
+/// Cache entries of other versions are never reused, since the extraction might have changed.
+const EXTRACTOR_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Cache of extracted game dumps and encoded assets.
+///
+/// Extracted game dumps are keyed by the file they have been extracted from, so that the
+/// expensive decryption is skipped for a dump, which has been extracted before. Encoded
+/// assets are keyed by the hash of the asset file, so that only changed asset files have to
+/// be encoded again.
+///
+/// The cache is bounded by [`MAX_CACHE_SIZE`]. When an entry is used, it is recorded in an
+/// index, which is written by [`Cache::evict`].
+pub struct Cache {
+    dir: PathBuf,
+    index: Mutex<CacheIndex>,
+}
+
+/// When each cache entry has been used the last time in seconds since the Unix epoch.
+///
+/// Entries are given relative to the cache dir. Entries, which are missing in the index,
+/// are evicted first.
+#[derive(Default, Deserialize, Serialize)]
+struct CacheIndex {
+    last_used: BTreeMap<PathBuf, u64>,
+}
+
+impl Cache {
+    /// Opens the cache in the platform cache dir, if there is one.
+    pub fn open() -> Option<Self> {
+        let dir = dirs::cache_dir()?.join(CACHE_DIR_NAME);
+        // a missing or corrupt index only loses the order of eviction
+        let index = fs::read(dir.join("index.json"))
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        Some(Self {
+            dir,
+            index: Mutex::new(index),
+        })
+    }
+
+    /// Removes all cached game dumps and assets.
+    pub fn clear(&self) -> Result<()> {
+        if self.dir.exists() {
+            fs::remove_dir_all(&self.dir).with_path(&self.dir)?;
+        }
+        *self.index.lock().unwrap() = CacheIndex::default();
+        Ok(())
+    }
+
+    /// Evicts the least recently used entries, until the cache fits into [`MAX_CACHE_SIZE`],
+    /// and writes the index.
+    pub fn evict(&self) -> Result<()> {
+        let mut index = self.index.lock().unwrap();
+        let mut entries = vec![];
+        for entry in self.entries()? {
+            let size = entry_size(&self.dir.join(&entry))?;
+            let last_used = index.last_used.get(&entry).copied().unwrap_or_default();
+            entries.push((last_used, entry, size));
+        }
+        entries.sort();
+        let mut size: u64 = entries.iter().map(|(_, _, size)| size).sum();
+        let mut last_used = BTreeMap::new();
+        for (used, entry, entry_size) in entries {
+            if size > MAX_CACHE_SIZE {
+                let path = self.dir.join(&entry);
+                if path.is_dir() {
+                    fs::remove_dir_all(&path).with_path(&path)?;
+                } else {
+                    fs::remove_file(&path).with_path(&path)?;
+                }
+                size -= entry_size;
+            } else {
+                last_used.insert(entry, used);
+            }
+        }
+        index.last_used = last_used;
+
+        fs::create_dir_all(&self.dir).with_path(&self.dir)?;
+        let path = self.dir.join("index.json");
+        let data = serde_json::to_vec(&*index)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+            .with_path(&path)?;
+        fs::write(&path, data).with_path(&path)
+    }
+
+    /// Lists all cached romfs directories and asset bundles relative to the cache dir.
+    fn entries(&self) -> Result<Vec<PathBuf>> {
+        let mut entries = vec![];
+        let romfs_dir = self.dir.join("romfs");
+        for dir_entry in fs::read_dir(&romfs_dir).into_iter().flatten() {
+            let dir_entry = dir_entry.with_path(&romfs_dir)?;
+            entries.push(Path::new("romfs").join(dir_entry.file_name()));
+        }
+        let assets_dir = self.dir.join("assets");
+        for dir_entry in fs::read_dir(&assets_dir).into_iter().flatten() {
+            let prefix = dir_entry.with_path(&assets_dir)?.file_name();
+            let dir = assets_dir.join(&prefix);
+            for dir_entry in fs::read_dir(&dir).with_path(&dir)? {
+                let dir_entry = dir_entry.with_path(&dir)?;
+                entries.push(
+                    Path::new("assets")
+                        .join(&prefix)
+                        .join(dir_entry.file_name()),
+                );
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Records that an entry has been used, so that it is evicted last.
+    fn touch(&self, entry: PathBuf) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        self.index.lock().unwrap().last_used.insert(entry, now);
+    }
+
+    /// Returns the cached romfs of a game dump, if it has been extracted before.
+    pub fn romfs(&self, key: &str) -> Option<PathBuf> {
+        let romfs_dir = self.dir.join("romfs").join(key);
+        if romfs_dir.is_dir() {
+            self.touch(Path::new("romfs").join(key));
+            Some(romfs_dir)
+        } else {
+            None
+        }
+    }
+
+    /// Stores the `Model` and `Pack` directories of an extracted romfs and returns the
+    /// cached romfs directory.
+    pub fn store_romfs(&self, key: &str, romfs_dir: &Path) -> Result<PathBuf> {
+        let cached_dir = self.dir.join("romfs").join(key);
+        // entries are written to a temporary directory first, so that an aborted extraction
+        // never leaves an incomplete romfs behind
+        let tmp_dir = self.dir.join("romfs").join(format!("{}.tmp", key));
+        if tmp_dir.exists() {
+            fs::remove_dir_all(&tmp_dir).with_path(&tmp_dir)?;
+        }
+        for asset_dir in ["Model", "Pack"] {
+            let from = romfs_dir.join(asset_dir);
+            if from.is_dir() {
+                copy_dir(&from, &tmp_dir.join(asset_dir))?;
+            }
+        }
+        fs::create_dir_all(&tmp_dir).with_path(&tmp_dir)?;
+        fs::rename(&tmp_dir, &cached_dir).with_path(&cached_dir)?;
+        self.touch(Path::new("romfs").join(key));
+        Ok(cached_dir)
+    }
+
+    /// Returns the cached bundle entries of an asset file.
+    pub fn assets(&self, key: &str) -> Option<Vec<u8>> {
+        let data = fs::read(self.dir.join(assets_entry(key))).ok()?;
+        self.touch(assets_entry(key));
+        Some(data)
+    }
+
+    pub fn store_assets(&self, key: &str, data: &[u8]) -> Result<()> {
+        let path = self.dir.join(assets_entry(key));
+        let tmp_path = path.with_extension("tmp");
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).with_path(dir)?;
+        }
+        fs::write(&tmp_path, data).with_path(&tmp_path)?;
+        fs::rename(&tmp_path, &path).with_path(&path)?;
+        self.touch(assets_entry(key));
+        Ok(())
+    }
+}
+
+/// Cache entry of the encoded assets of an asset file relative to the cache dir.
+fn assets_entry(key: &str) -> PathBuf {
+    Path::new("assets")
+        .join(&key[..2])
+        .join(format!("{}.tar", key))
+}
+
+/// Size of a cache entry, which is either a file or a directory.
+fn entry_size(path: &Path) -> Result<u64> {
+    let metadata = fs::metadata(path).with_path(path)?;
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+    let mut size = 0;
+    for dir_entry in fs::read_dir(path).with_path(path)? {
+        size += entry_size(&dir_entry.with_path(path)?.path())?;
+    }
+    Ok(size)
+}
+
+/// Keys a file by its path, size and modification time together with the extractor version.
+///
+/// Game dumps are too large to be hashed on every run, so only their first and last
+/// [`PARTIAL_HASH_SIZE`] bytes are hashed, which catches dumps replaced in place with the
+/// same modification time.
+pub fn file_key(path: &Path) -> Result<String> {
+    let path = path.canonicalize().with_path(path)?;
+    let metadata = fs::metadata(&path).with_path(&path)?;
+    let mtime = metadata
+        .modified()
+        .with_path(&path)?
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+
+    let mut hasher = Sha256::new();
+    hasher.update(EXTRACTOR_VERSION);
+    hasher.update([0]);
+    hasher.update(path.to_string_lossy().as_bytes());
+    hasher.update([0]);
+    hasher.update(metadata.len().to_le_bytes());
+    hasher.update(mtime.as_nanos().to_le_bytes());
+
+    let mut file = File::open(&path).with_path(&path)?;
+    let head = metadata.len().min(PARTIAL_HASH_SIZE);
+    io::copy(&mut (&mut file).take(head), &mut hasher).with_path(&path)?;
+    let tail = PARTIAL_HASH_SIZE.min(metadata.len() - head);
+    file.seek(SeekFrom::End(-(tail as i64))).with_path(&path)?;
+    io::copy(&mut file.take(tail), &mut hasher).with_path(&path)?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Hashes data together with the extractor version and the options it is processed with.
+pub fn hash_data(data: &[u8], options: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(EXTRACTOR_VERSION);
+    hasher.update(options);
+    hasher.update([0]);
+    hasher.update(data);
+    hex::encode(hasher.finalize())
+}
+
+fn copy_dir(from: &Path, to: &Path) -> Result<()> {
+    fs::create_dir_all(to).with_path(to)?;
+    for dir_entry in fs::read_dir(from).with_path(from)? {
+        let dir_entry = dir_entry.with_path(from)?;
+        let path = dir_entry.path();
+        let target = to.join(dir_entry.file_name());
+        if dir_entry.file_type().with_path(&path)?.is_dir() {
+            copy_dir(&path, &target)?;
+        } else {
+            fs::copy(&path, &target).with_path(&path)?;
+        }
+    }
+    Ok(())
+}
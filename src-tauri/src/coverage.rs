@@ -0,0 +1,76 @@
+use crate::input::InputKind;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+};
+
+/// Which required files are contained in an input.
+#[derive(Clone, Debug, Serialize)]
+pub struct InputCoverage {
+    pub input: PathBuf,
+    /// Whether the content of the input is known. Game dumps are only listed with prod keys or
+    /// once extracted.
+    pub listed: bool,
+    pub file_count: usize,
+    pub required_files: Vec<String>,
+}
+
+/// Coverage of the required files by all selected inputs.
+#[derive(Clone, Debug, Serialize)]
+pub struct Coverage {
+    pub inputs: Vec<InputCoverage>,
+    /// The input, which supplies each required file, if any.
+    pub suppliers: BTreeMap<String, Option<PathBuf>>,
+}
+
+impl Coverage {
+    /// Computes the coverage of the given inputs, which have to be in layer order, so that
+    /// a required file is supplied by the highest input containing it.
+    pub fn new(
+        inputs: &[(PathBuf, InputKind)],
+        file_content: &HashMap<PathBuf, Vec<String>>,
+        required_files: &[String],
+    ) -> Self {
+        let mut suppliers: BTreeMap<_, _> = required_files
+            .iter()
+            .map(|required_file| (required_file.clone(), None))
+            .collect();
+        let inputs = inputs
+            .iter()
+            .map(|(input, _)| {
+                let content = file_content.get(input);
+                let required_files: Vec<_> = content
+                    .into_iter()
+                    .flatten()
+                    .filter(|name| suppliers.contains_key(*name))
+                    .cloned()
+                    .collect();
+                for required_file in required_files.iter() {
+                    suppliers.insert(required_file.clone(), Some(input.clone()));
+                }
+                InputCoverage {
+                    input: input.clone(),
+                    listed: content.is_some(),
+                    file_count: content.map(Vec::len).unwrap_or_default(),
+                    required_files,
+                }
+            })
+            .collect();
+        Self { inputs, suppliers }
+    }
+
+    /// Whether any input could not be listed, e.g. a compressed game dump, which has not been
+    /// extracted.
+    pub fn has_unlisted_inputs(&self) -> bool {
+        self.inputs.iter().any(|input| !input.listed)
+    }
+
+    pub fn missing_files(&self) -> Vec<String> {
+        self.suppliers
+            .iter()
+            .filter(|(_, supplier)| supplier.is_none())
+            .map(|(required_file, _)| required_file.clone())
+            .collect()
+    }
+}
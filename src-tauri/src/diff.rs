@@ -0,0 +1,182 @@
+use crate::{layers::Layer, ninres::decode_textures, Result};
+use image::{codecs::png::PngEncoder, DynamicImage, ImageEncoder, RgbaImage};
+use ninres::NinRes;
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+/// Maximum width and height of texture thumbnails.
+const THUMBNAIL_SIZE: u32 = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TextureStatus {
+    Added,
+    Removed,
+    Changed,
+}
+
+/// Pixel differences between the texture of the base game and of a mod.
+#[derive(Clone, Debug, Serialize)]
+pub struct PixelDiff {
+    pub size_changed: bool,
+    pub changed_pixels: u64,
+    pub total_pixels: u64,
+    /// Largest difference of a single channel.
+    pub max_delta: u8,
+    /// Average difference per channel over all pixels.
+    pub mean_delta: f64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TextureDiff {
+    pub asset: PathBuf,
+    pub texture: String,
+    pub status: TextureStatus,
+    pub pixel_diff: Option<PixelDiff>,
+    /// PNG data URL of the modded texture, or of the base game texture if it has been removed.
+    pub thumbnail: Option<String>,
+}
+
+/// All textures, which are changed by a mod compared to the base game.
+#[derive(Clone, Debug, Serialize)]
+pub struct ModDiff {
+    pub input: PathBuf,
+    pub textures: Vec<TextureDiff>,
+}
+
+/// Compares all assets of a mod with the base game, which may consist of multiple layers,
+/// e.g. the game and an update.
+pub fn diff_mod(base: &[Layer], layer: &Layer) -> Result<ModDiff> {
+    let mut textures = vec![];
+    for asset in layer.assets()? {
+        let mod_data = match layer.read(&asset)? {
+            Some(data) => data,
+            None => continue,
+        };
+        let mut base_data = None;
+        for base_layer in base.iter().rev() {
+            base_data = base_layer.read(&asset)?;
+            if base_data.is_some() {
+                break;
+            }
+        }
+        if base_data.as_ref() == Some(&mod_data) {
+            continue;
+        }
+
+        let base_textures = base_data
+            .as_deref()
+            .map(|data| read_textures(data, &asset))
+            .unwrap_or_default();
+        let mod_textures = read_textures(&mod_data, &asset);
+        textures.extend(diff_textures(&asset, &base_textures, &mod_textures)?);
+    }
+    Ok(ModDiff {
+        input: layer.input.clone(),
+        textures,
+    })
+}
+
+fn read_textures(data: &[u8], asset: &Path) -> BTreeMap<String, RgbaImage> {
+    let mut textures = BTreeMap::new();
+    if let Ok(ninres) = data.as_ninres() {
+        decode_textures(&ninres, asset.to_path_buf(), &mut textures);
+    }
+    textures
+}
+
+fn diff_textures(
+    asset: &Path,
+    base: &BTreeMap<String, RgbaImage>,
+    modded: &BTreeMap<String, RgbaImage>,
+) -> Result<Vec<TextureDiff>> {
+    let mut diffs = vec![];
+    for (name, image) in modded.iter() {
+        let (status, pixel_diff) = match base.get(name) {
+            Some(base_image) => match diff_pixels(base_image, image) {
+                Some(pixel_diff) => (TextureStatus::Changed, Some(pixel_diff)),
+                None => continue,
+            },
+            None => (TextureStatus::Added, None),
+        };
+        diffs.push(TextureDiff {
+            asset: asset.to_path_buf(),
+            texture: name.clone(),
+            status,
+            pixel_diff,
+            thumbnail: Some(thumbnail(image)?),
+        });
+    }
+    for (name, image) in base.iter() {
+        if !modded.contains_key(name) {
+            diffs.push(TextureDiff {
+                asset: asset.to_path_buf(),
+                texture: name.clone(),
+                status: TextureStatus::Removed,
+                pixel_diff: None,
+                thumbnail: Some(thumbnail(image)?),
+            });
+        }
+    }
+    Ok(diffs)
+}
+
+/// Compares two textures pixel by pixel and returns `None`, if they are identical.
+fn diff_pixels(base: &RgbaImage, modded: &RgbaImage) -> Option<PixelDiff> {
+    if base.dimensions() != modded.dimensions() {
+        let total_pixels = modded.width() as u64 * modded.height() as u64;
+        return Some(PixelDiff {
+            size_changed: true,
+            changed_pixels: total_pixels,
+            total_pixels,
+            max_delta: u8::MAX,
+            mean_delta: u8::MAX as f64,
+        });
+    }
+
+    let mut changed_pixels = 0u64;
+    let mut max_delta = 0u8;
+    let mut delta_sum = 0u64;
+    for (base_pixel, mod_pixel) in base.pixels().zip(modded.pixels()) {
+        let mut changed = false;
+        for (b, m) in base_pixel.0.iter().zip(mod_pixel.0.iter()) {
+            let delta = if b > m { b - m } else { m - b };
+            if delta > 0 {
+                changed = true;
+                max_delta = max_delta.max(delta);
+                delta_sum += delta as u64;
+            }
+        }
+        if changed {
+            changed_pixels += 1;
+        }
+    }
+    if changed_pixels == 0 {
+        return None;
+    }
+
+    let total_pixels = modded.width() as u64 * modded.height() as u64;
+    Some(PixelDiff {
+        size_changed: false,
+        changed_pixels,
+        total_pixels,
+        max_delta,
+        mean_delta: delta_sum as f64 / (total_pixels * 4) as f64,
+    })
+}
+
+fn thumbnail(image: &RgbaImage) -> Result<String> {
+    let thumbnail =
+        DynamicImage::ImageRgba8(image.clone()).thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE);
+    let mut data = vec![];
+    PngEncoder::new(&mut data).write_image(
+        thumbnail.as_bytes(),
+        thumbnail.width(),
+        thumbnail.height(),
+        thumbnail.color(),
+    )?;
+    Ok(format!("data:image/png;base64,{}", base64::encode(data)))
+}
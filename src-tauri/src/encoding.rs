@@ -0,0 +1,160 @@
+use crate::{ktx2, Result};
+use glob::Pattern;
+use image::{
+    codecs::{
+        avif::AvifEncoder,
+        png::{CompressionType, FilterType, PngEncoder},
+        webp::WebPEncoder,
+    },
+    ColorType, ImageEncoder,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+
+/// Encoder speed of AVIF images, where 1 is the slowest and 10 the fastest.
+const AVIF_SPEED: u8 = 6;
+/// Quality of AVIF images, which are always encoded lossy.
+const AVIF_QUALITY: u8 = 90;
+/// Optimization level of the optional oxipng pass.
+const OXIPNG_PRESET: u8 = 2;
+
+/// Formats in which extracted images can be encoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageEncoding {
+    #[default]
+    Png,
+    /// Lossless WebP, which is usually a lot smaller than PNG.
+    #[serde(rename = "webp")]
+    WebP,
+    /// Lossy AVIF.
+    Avif,
+    /// Zstandard supercompressed KTX2 with uncompressed RGBA8 data, which can be uploaded to
+    /// the GPU without decoding it. This is not a Basis Universal texture, so it stays as
+    /// large as RGBA8 in GPU memory.
+    #[serde(rename = "ktx2_rgba8", alias = "ktx2")]
+    Ktx2Rgba8,
+}
+
+impl ImageEncoding {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageEncoding::Png => "png",
+            ImageEncoding::WebP => "webp",
+            ImageEncoding::Avif => "avif",
+            ImageEncoding::Ktx2Rgba8 => "ktx2",
+        }
+    }
+
+    /// Encodes RGBA8 image data in this format.
+    ///
+    /// PNG images are filtered adaptively per row, which compresses better than no filtering
+    /// even at the default compression level, so the slow best compression is not needed.
+    pub fn encode(&self, data: &[u8], width: u32, height: u32) -> Result<Vec<u8>> {
+        let mut image_data = vec![];
+        match self {
+            ImageEncoding::Png => PngEncoder::new_with_quality(
+                &mut image_data,
+                CompressionType::Default,
+                FilterType::Adaptive,
+            )
+            .write_image(data, width, height, ColorType::Rgba8)?,
+            ImageEncoding::WebP => WebPEncoder::new_lossless(&mut image_data).encode(
+                data,
+                width,
+                height,
+                ColorType::Rgba8,
+            )?,
+            ImageEncoding::Avif => {
+                AvifEncoder::new_with_speed_quality(&mut image_data, AVIF_SPEED, AVIF_QUALITY)
+                    .write_image(data, width, height, ColorType::Rgba8)?
+            }
+            ImageEncoding::Ktx2Rgba8 => ktx2::write_rgba8(&mut image_data, data, width, height)?,
+        }
+        Ok(image_data)
+    }
+}
+
+/// Selects the encoding of all images, whose name matches the glob pattern.
+///
+/// Patterns are matched against the name of the image as it would be stored as PNG, e.g.
+/// `*_Field_*.png` matches all tiles.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AssetRule {
+    pub pattern: String,
+    pub encoding: ImageEncoding,
+}
+
+/// Compiled asset rules, where the first matching rule wins.
+///
+/// Images, which are not matched by any rule, are encoded as PNG.
+#[derive(Clone, Debug, Default)]
+pub struct AssetRules {
+    rules: Vec<(Pattern, ImageEncoding)>,
+    /// Whether PNG images are recompressed by oxipng, which is slow, but saves some bytes.
+    optimize_png: bool,
+    fingerprint: String,
+}
+
+impl AssetRules {
+    pub fn new(rules: &[AssetRule], optimize_png: bool) -> Result<Self> {
+        let mut fingerprint = format!("optimize_png={}\n", optimize_png);
+        let rules = rules
+            .iter()
+            .map(|rule| {
+                writeln!(
+                    fingerprint,
+                    "{}={}",
+                    rule.pattern,
+                    rule.encoding.extension()
+                )
+                .unwrap();
+                Ok((Pattern::new(&rule.pattern)?, rule.encoding))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            rules,
+            optimize_png,
+            fingerprint,
+        })
+    }
+
+    pub fn encoding(&self, file_name: &str) -> ImageEncoding {
+        self.rules
+            .iter()
+            .find(|(pattern, _)| pattern.matches(file_name))
+            .map(|(_, encoding)| *encoding)
+            .unwrap_or_default()
+    }
+
+    /// Encodes an image with the encoding selected for its name and returns the name with
+    /// the extension of the encoding.
+    pub fn encode(
+        &self,
+        file_name: &str,
+        data: &[u8],
+        width: u32,
+        height: u32,
+    ) -> Result<(PathBuf, Vec<u8>)> {
+        let encoding = self.encoding(file_name);
+        let mut image_data = encoding.encode(data, width, height)?;
+        if self.optimize_png && encoding == ImageEncoding::Png {
+            image_data = oxipng::optimize_from_memory(
+                &image_data,
+                &oxipng::Options::from_preset(OXIPNG_PRESET),
+            )?;
+        }
+        Ok((
+            Path::new(file_name).with_extension(encoding.extension()),
+            image_data,
+        ))
+    }
+
+    /// Uniquely describes the rules, so that assets are encoded again, if the rules change.
+    pub fn fingerprint(&self) -> &str {
+        &self.fingerprint
+    }
+}
@@ -1,37 +1,218 @@
 use glob::{GlobError, PatternError};
 use nfd2::error::NfdError;
-use serde::Serialize;
-use std::io;
+use serde::{Serialize, Serializer};
+use serde_json::{json, Value};
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
 use thiserror::Error;
+use zip::result::ZipError;
 
-#[derive(Debug, Error, Serialize)]
+#[derive(Debug, Error)]
 pub enum Error {
-    #[error("[Nfd]: {}", 0)]
+    #[error("[Nfd]: {0}")]
     Nfd(String),
-    #[error("[Io]: {}", 0)]
-    Io(String),
-    #[error("[Tauri]: {}", 0)]
+    #[error("[Io]: {message}{}", display_path(.path))]
+    Io {
+        message: String,
+        path: Option<PathBuf>,
+    },
+    #[error("[Tauri]: {0}")]
     Tauri(String),
-    #[error("[TauriApi]: {}", 0)]
+    #[error("[TauriApi]: {0}")]
     TauriApi(String),
-    #[error("[NinRes]: {}", 0)]
+    #[error("[NinRes]: {0}")]
     NinRes(String),
-    #[error("[Image]: {}", 0)]
+    #[error("[Image]: {0}")]
     Image(String),
-    #[error("[Glob]: {}", 0)]
-    Glob(String),
-    #[error("[Pattern]: {}", 0)]
+    #[error("[Pattern]: {0}")]
     Pattern(String),
+    #[error("[Zip]: {message}{}", display_path(.path))]
+    Zip {
+        message: String,
+        path: Option<PathBuf>,
+    },
+    #[error("[SevenZ]: {message}{}", display_path(.path))]
+    SevenZ {
+        message: String,
+        path: Option<PathBuf>,
+    },
+    #[error("[Rar]: {message}{}", display_path(.path))]
+    Rar {
+        message: String,
+        path: Option<PathBuf>,
+    },
     #[error("File select canceled")]
     FileSelectCanceled,
-    #[error("File extension not supported")]
+    #[error("File type not supported")]
     FileExtensionUnsupported,
     #[error("Prod key not set")]
     ProdKeyNotSet,
-    #[error("{}", 0)]
+    #[error("No base game selected")]
+    BaseGameMissing,
+    #[error("Required files missing: {}", .0.join(", "))]
     RequiredFilesMissing(Vec<String>),
-    #[error("Dir not found: {}", 0)]
+    #[error("Dir not found: {0}")]
     DirNotFound(String),
+    #[error("Key missing in prod.keys: {0}")]
+    KeyMissing(String),
+    #[error("Key invalid: {0}")]
+    KeyInvalid(String),
+    #[error(
+        "Title key missing for rights ID {0}, since the dump contains no common ticket for it"
+    )]
+    TitleKeyMissing(String),
+    #[error("NCA header invalid: {}", .0.display())]
+    NcaHeaderInvalid(PathBuf),
+    #[error("Base bundles have to be a tar, tar.gz or tar.zst, which contain a manifest")]
+    BaseBundleFormatUnsupported,
+    #[error("Assets could not be bundled: {}", .0.join(", "))]
+    AssetsFailed(Vec<String>),
+    #[error("[Sidecar]: {tool} exited with code {code:?}: {stderr}")]
+    Sidecar {
+        tool: String,
+        code: Option<i32>,
+        stderr: String,
+    },
+}
+
+fn display_path(path: &Option<PathBuf>) -> String {
+    path.as_ref()
+        .map(|path| format!(" ({})", path.display()))
+        .unwrap_or_default()
+}
+
+impl Error {
+    /// Stable error code, which the frontend can rely on e.g. for localization.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::Nfd(_) => "nfd",
+            Error::Io { .. } => "io",
+            Error::Tauri(_) => "tauri",
+            Error::TauriApi(_) => "tauri_api",
+            Error::NinRes(_) => "ninres",
+            Error::Image(_) => "image",
+            Error::Pattern(_) => "pattern",
+            Error::Zip { .. } => "zip",
+            Error::SevenZ { .. } => "seven_z",
+            Error::Rar { .. } => "rar",
+            Error::FileSelectCanceled => "file_select_canceled",
+            Error::FileExtensionUnsupported => "file_extension_unsupported",
+            Error::ProdKeyNotSet => "prod_key_not_set",
+            Error::BaseGameMissing => "base_game_missing",
+            Error::RequiredFilesMissing(_) => "required_files_missing",
+            Error::DirNotFound(_) => "dir_not_found",
+            Error::KeyMissing(_) => "key_missing",
+            Error::KeyInvalid(_) => "key_invalid",
+            Error::TitleKeyMissing(_) => "title_key_missing",
+            Error::NcaHeaderInvalid(_) => "nca_header_invalid",
+            Error::BaseBundleFormatUnsupported => "base_bundle_format_unsupported",
+            Error::AssetsFailed(_) => "assets_failed",
+            Error::Sidecar { .. } => "sidecar",
+        }
+    }
+
+    /// The file that caused this error, if known.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Error::Io { path, .. }
+            | Error::Zip { path, .. }
+            | Error::SevenZ { path, .. }
+            | Error::Rar { path, .. } => path.as_deref(),
+            Error::NcaHeaderInvalid(path) => Some(path),
+            _ => None,
+        }
+    }
+
+    /// Machine readable details of this error.
+    pub fn details(&self) -> Option<Value> {
+        match self {
+            Error::RequiredFilesMissing(files) | Error::AssetsFailed(files) => Some(json!(files)),
+            Error::DirNotFound(dir) => Some(json!(dir)),
+            Error::KeyMissing(key) | Error::KeyInvalid(key) => Some(json!(key)),
+            Error::TitleKeyMissing(rights_id) => Some(json!(rights_id)),
+            Error::Sidecar { tool, code, stderr } => Some(json!({
+                "tool": tool,
+                "code": code,
+                "stderr": stderr,
+            })),
+            _ => None,
+        }
+    }
+
+    /// Attaches the given file path to I/O and archive errors.
+    pub fn with_path(self, file: &Path) -> Error {
+        match self {
+            Error::Io {
+                message,
+                path: None,
+            } => Error::Io {
+                message,
+                path: Some(file.to_path_buf()),
+            },
+            Error::Zip {
+                message,
+                path: None,
+            } => Error::Zip {
+                message,
+                path: Some(file.to_path_buf()),
+            },
+            Error::SevenZ {
+                message,
+                path: None,
+            } => Error::SevenZ {
+                message,
+                path: Some(file.to_path_buf()),
+            },
+            Error::Rar {
+                message,
+                path: None,
+            } => Error::Rar {
+                message,
+                path: Some(file.to_path_buf()),
+            },
+            err => err,
+        }
+    }
+}
+
+pub trait ResultExt<T> {
+    fn with_path(self, path: &Path) -> crate::Result<T>;
+}
+
+impl<T, E> ResultExt<T> for Result<T, E>
+where
+    E: Into<Error>,
+{
+    fn with_path(self, path: &Path) -> crate::Result<T> {
+        self.map_err(|err| err.into().with_path(path))
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorPayload<'a> {
+    kind: &'static str,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<&'a Path>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    details: Option<Value>,
+}
+
+impl Serialize for Error {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        ErrorPayload {
+            kind: self.kind(),
+            message: self.to_string(),
+            path: self.path(),
+            details: self.details(),
+        }
+        .serialize(serializer)
+    }
 }
 
 impl From<NfdError> for Error {
@@ -42,19 +223,22 @@ impl From<NfdError> for Error {
 
 impl From<io::Error> for Error {
     fn from(err: io::Error) -> Error {
-        Error::Io(format!("{:?}", err))
+        Error::Io {
+            message: err.to_string(),
+            path: None,
+        }
     }
 }
 
 impl From<tauri::Error> for Error {
     fn from(err: tauri::Error) -> Error {
-        Error::Tauri(format!("{:?}", err))
+        Error::Tauri(err.to_string())
     }
 }
 
 impl From<tauri::api::Error> for Error {
     fn from(err: tauri::api::Error) -> Error {
-        Error::TauriApi(format!("{:?}", err))
+        Error::TauriApi(err.to_string())
     }
 }
 
@@ -66,18 +250,55 @@ impl From<ninres::NinResError> for Error {
 
 impl From<image::ImageError> for Error {
     fn from(err: image::ImageError) -> Error {
-        Error::Image(format!("{:?}", err))
+        Error::Image(err.to_string())
+    }
+}
+
+impl From<oxipng::PngError> for Error {
+    fn from(err: oxipng::PngError) -> Error {
+        Error::Image(err.to_string())
     }
 }
 
 impl From<GlobError> for Error {
     fn from(err: GlobError) -> Error {
-        Error::Glob(format!("{:?}", err))
+        let path = err.path().to_path_buf();
+        Error::Io {
+            message: err.error().to_string(),
+            path: Some(path),
+        }
     }
 }
 
 impl From<PatternError> for Error {
     fn from(err: PatternError) -> Error {
-        Error::Pattern(format!("{:?}", err))
+        Error::Pattern(err.to_string())
+    }
+}
+
+impl From<ZipError> for Error {
+    fn from(err: ZipError) -> Error {
+        Error::Zip {
+            message: err.to_string(),
+            path: None,
+        }
+    }
+}
+
+impl From<sevenz_rust::Error> for Error {
+    fn from(err: sevenz_rust::Error) -> Error {
+        Error::SevenZ {
+            message: err.to_string(),
+            path: None,
+        }
+    }
+}
+
+impl From<unrar::error::UnrarError> for Error {
+    fn from(err: unrar::error::UnrarError) -> Error {
+        Error::Rar {
+            message: err.to_string(),
+            path: None,
+        }
     }
 }
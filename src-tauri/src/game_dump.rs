@@ -0,0 +1,350 @@
+use crate::{
+    error::{Error, ResultExt},
+    keys::Keys,
+    nca::NcaHeader,
+    ncz::apply_ctr,
+    romfs::list_romfs,
+    ticket::Ticket,
+    Result,
+};
+use aes::{
+    cipher::{generic_array::GenericArray, KeyInit},
+    Aes128,
+};
+use std::{
+    collections::HashMap,
+    convert::TryInto,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+/// Offsets of the card header in dumps without and with the initial key area.
+const XCI_HEADER_OFFSETS: [u64; 2] = [0x100, 0x1100];
+const XCI_ROOT_PARTITION_OFFSET: u64 = 0x30;
+const HFS0_ENTRY_SIZE: usize = 0x40;
+const PFS0_ENTRY_SIZE: usize = 0x18;
+const MAX_PARTITION_HEADER_SIZE: usize = 1024 * 1024;
+
+/// A file within the partition of a game dump.
+struct PartitionEntry {
+    name: String,
+    /// Offset of the file within the game dump.
+    offset: u64,
+    size: u64,
+}
+
+/// Lists the romfs of the program within an XCI.
+///
+/// Returns `None` if the program is compressed, since its romfs could then only be listed
+/// by decompressing it.
+pub fn list_xci(file: &Path, keys: &Keys) -> Result<Option<Vec<PathBuf>>> {
+    let mut reader = File::open(file).with_path(file)?;
+    let mut header_offset = None;
+    for offset in XCI_HEADER_OFFSETS.iter() {
+        if read_at(&mut reader, *offset, 4).with_path(file)? == b"HEAD" {
+            header_offset = Some(*offset);
+            break;
+        }
+    }
+    let header_offset = header_offset.ok_or_else(|| invalid_data(file, "XCI header missing"))?;
+    let root_offset = u64::from_le_bytes(
+        read_at(&mut reader, header_offset + XCI_ROOT_PARTITION_OFFSET, 8)
+            .with_path(file)?
+            .try_into()
+            .unwrap(),
+    );
+    // the root partition is located relative to the card header without the key area
+    let root_offset = (header_offset - XCI_HEADER_OFFSETS[0])
+        .checked_add(root_offset)
+        .ok_or_else(|| invalid_data(file, "XCI root partition out of bounds"))?;
+    let root = read_partition(&mut reader, file, root_offset, b"HFS0", HFS0_ENTRY_SIZE)?;
+    let secure = root
+        .iter()
+        .find(|entry| entry.name == "secure")
+        .ok_or_else(|| invalid_data(file, "XCI secure partition missing"))?;
+    let entries = read_partition(&mut reader, file, secure.offset, b"HFS0", HFS0_ENTRY_SIZE)?;
+    list_program_romfs(&mut reader, file, &entries, keys)
+}
+
+/// Lists the romfs of the program within an NSP.
+///
+/// Returns `None` if the program is compressed, since its romfs could then only be listed
+/// by decompressing it.
+pub fn list_nsp(file: &Path, keys: &Keys) -> Result<Option<Vec<PathBuf>>> {
+    let mut reader = File::open(file).with_path(file)?;
+    let entries = read_partition(&mut reader, file, 0, b"PFS0", PFS0_ENTRY_SIZE)?;
+    list_program_romfs(&mut reader, file, &entries, keys)
+}
+
+/// Reads the entries of an HFS0 or PFS0 partition, which only differ in their entry size.
+fn read_partition(
+    reader: &mut File,
+    file: &Path,
+    offset: u64,
+    magic: &[u8],
+    entry_size: usize,
+) -> Result<Vec<PartitionEntry>> {
+    let header = read_at(reader, offset, 0x10).with_path(file)?;
+    if &header[..4] != magic {
+        return Err(invalid_data(file, "partition header invalid"));
+    }
+    let entry_count = u32::from_le_bytes(header[4..8].try_into().unwrap()) as usize;
+    let string_table_size = u32::from_le_bytes(header[8..12].try_into().unwrap()) as usize;
+    let tables_size = entry_count
+        .checked_mul(entry_size)
+        .and_then(|size| size.checked_add(string_table_size))
+        .filter(|size| *size <= MAX_PARTITION_HEADER_SIZE)
+        .ok_or_else(|| invalid_data(file, "partition header too large"))?;
+    let tables = read_at(reader, offset + 0x10, tables_size).with_path(file)?;
+    let (entry_table, string_table) = tables.split_at(entry_count * entry_size);
+    let data_offset = offset + 0x10 + tables_size as u64;
+
+    entry_table
+        .chunks(entry_size)
+        .map(|entry| {
+            let field =
+                |offset: usize| u64::from_le_bytes(entry[offset..offset + 8].try_into().unwrap());
+            let name_offset = u32::from_le_bytes(entry[0x10..0x14].try_into().unwrap()) as usize;
+            let name = string_table
+                .get(name_offset..)
+                .and_then(|name| name.split(|b| *b == 0).next())
+                .ok_or_else(|| invalid_data(file, "partition entry name out of bounds"))?;
+            Ok(PartitionEntry {
+                name: String::from_utf8_lossy(name).to_string(),
+                offset: data_offset
+                    .checked_add(field(0))
+                    .ok_or_else(|| invalid_data(file, "partition entry out of bounds"))?,
+                size: field(8),
+            })
+        })
+        .collect()
+}
+
+/// Finds the NCA, which contains the romfs of the program, and lists its files.
+fn list_program_romfs(
+    reader: &mut File,
+    file: &Path,
+    entries: &[PartitionEntry],
+    keys: &Keys,
+) -> Result<Option<Vec<PathBuf>>> {
+    if entries.iter().any(|entry| entry.name.ends_with(".ncz")) {
+        return Ok(None);
+    }
+
+    let mut title_keys = HashMap::new();
+    for entry in entries.iter().filter(|entry| entry.name.ends_with(".tik")) {
+        let size = entry.size.min(MAX_PARTITION_HEADER_SIZE as u64) as usize;
+        if let Some(ticket) = Ticket::parse(&read_at(reader, entry.offset, size).with_path(file)?) {
+            title_keys.insert(ticket.rights_id, ticket.title_key);
+        }
+    }
+
+    for entry in entries.iter().filter(|entry| entry.name.ends_with(".nca")) {
+        reader.seek(SeekFrom::Start(entry.offset)).with_path(file)?;
+        let header = NcaHeader::read(reader, file, keys)?;
+        let section = match header.program_romfs() {
+            Some(section) => section,
+            None => continue,
+        };
+        let cipher = match section.counter {
+            Some(_) => Some(Aes128::new(GenericArray::from_slice(
+                &header.content_key(keys, &title_keys)?,
+            ))),
+            None => None,
+        };
+        let romfs_offset = entry
+            .offset
+            .checked_add(section.offset)
+            .ok_or_else(|| invalid_data(file, "romfs out of bounds"))?;
+        let names = list_romfs(section.size, |offset, len| {
+            let mut data = read_at(reader, romfs_offset + offset, len)?;
+            if let (Some(cipher), Some(counter)) = (&cipher, &section.counter) {
+                apply_ctr(cipher, counter, section.offset + offset, &mut data);
+            }
+            Ok(data)
+        })
+        .with_path(file)?;
+        return Ok(Some(
+            names
+                .into_iter()
+                .map(|name| Path::new("romfs").join(name))
+                .collect(),
+        ));
+    }
+    Err(Error::DirNotFound("romfs".to_string()))
+}
+
+fn read_at(reader: &mut File, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+    reader.seek(SeekFrom::Start(offset))?;
+    let mut data = vec![0; len];
+    reader.read_exact(&mut data)?;
+    Ok(data)
+}
+
+fn invalid_data(file: &Path, message: &str) -> Error {
+    Error::from(io::Error::new(io::ErrorKind::InvalidData, message)).with_path(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        nca::tests::{encrypt_header, read_keys},
+        romfs::tests::romfs,
+    };
+    use aes::cipher::BlockEncrypt;
+    use std::fs;
+    use tempfile::tempdir;
+
+    const HEADER_KEY: [u8; 32] = [1; 32];
+    const KEY_AREA_KEY: [u8; 16] = [2; 16];
+    const TITLEKEK: [u8; 16] = [3; 16];
+    const CONTENT_KEY: [u8; 16] = [4; 16];
+    const RIGHTS_ID: [u8; 16] = [5; 16];
+    const ROMFS_OFFSET: usize = 0x4000;
+
+    fn encrypt_key(key: &[u8; 16], data: &[u8; 16]) -> [u8; 16] {
+        let mut block = GenericArray::clone_from_slice(data);
+        Aes128::new(GenericArray::from_slice(key)).encrypt_block(&mut block);
+        block.into()
+    }
+
+    /// Builds an NCA, whose romfs is encrypted with the content key.
+    fn nca(content_type: u8, romfs: &[u8], rights_id: Option<[u8; 16]>) -> Vec<u8> {
+        let mut header = vec![0; 0xc00];
+        header[0x200..0x204].copy_from_slice(b"NCA3");
+        header[0x205] = content_type;
+        let media_start = (ROMFS_OFFSET / 0x200) as u32;
+        let media_end = media_start + (romfs.len() as u32 + 0x1ff) / 0x200;
+        header[0x240..0x244].copy_from_slice(&media_start.to_le_bytes());
+        header[0x244..0x248].copy_from_slice(&media_end.to_le_bytes());
+        match rights_id {
+            Some(rights_id) => header[0x230..0x240].copy_from_slice(&rights_id),
+            None => header[0x320..0x330].copy_from_slice(&encrypt_key(&KEY_AREA_KEY, &CONTENT_KEY)),
+        }
+        let fs_header = &mut header[0x400..0x600];
+        fs_header[0x3] = 3;
+        fs_header[0x4] = 3;
+        fs_header[0x98..0xa0].copy_from_slice(&(romfs.len() as u64).to_le_bytes());
+        fs_header[0x140..0x148].copy_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]);
+        encrypt_header(&mut header, &HEADER_KEY);
+
+        let mut data = romfs.to_vec();
+        let counter = [8, 7, 6, 5, 4, 3, 2, 1, 0, 0, 0, 0, 0, 0, 0, 0];
+        let cipher = Aes128::new(GenericArray::from_slice(&CONTENT_KEY));
+        apply_ctr(&cipher, &counter, ROMFS_OFFSET as u64, &mut data);
+        header.resize(ROMFS_OFFSET, 0);
+        header.extend(data);
+        header
+    }
+
+    /// Builds a common ticket, which contains the encrypted title key of the rights ID.
+    fn ticket() -> Vec<u8> {
+        let mut ticket = vec![0; 0x2c0];
+        ticket[..4].copy_from_slice(&0x10004u32.to_le_bytes());
+        ticket[0x180..0x190].copy_from_slice(&encrypt_key(&TITLEKEK, &CONTENT_KEY));
+        ticket[0x2a0..0x2b0].copy_from_slice(&RIGHTS_ID);
+        ticket
+    }
+
+    fn partition(magic: &[u8], entry_size: usize, files: &[(&str, Vec<u8>)]) -> Vec<u8> {
+        let mut entries = vec![];
+        let mut string_table = vec![];
+        let mut data: Vec<u8> = vec![];
+        for (name, file) in files.iter() {
+            let mut entry = vec![0; entry_size];
+            entry[..8].copy_from_slice(&(data.len() as u64).to_le_bytes());
+            entry[8..16].copy_from_slice(&(file.len() as u64).to_le_bytes());
+            entry[16..20].copy_from_slice(&(string_table.len() as u32).to_le_bytes());
+            entries.extend(entry);
+            string_table.extend(name.as_bytes());
+            string_table.push(0);
+            data.extend(file);
+        }
+        let mut partition = magic.to_vec();
+        partition.extend((files.len() as u32).to_le_bytes());
+        partition.extend((string_table.len() as u32).to_le_bytes());
+        partition.extend([0; 4]);
+        partition.extend(entries);
+        partition.extend(string_table);
+        partition.extend(data);
+        partition
+    }
+
+    fn keys(dir: &Path) -> Keys {
+        read_keys(
+            dir,
+            &[
+                ("header_key", &HEADER_KEY[..]),
+                ("key_area_key_application_00", &KEY_AREA_KEY[..]),
+                ("titlekek_00", &TITLEKEK[..]),
+            ],
+        )
+    }
+
+    fn expected_names() -> Vec<PathBuf> {
+        vec![
+            PathBuf::from("romfs/Model/MW_Field_plain.Nin_NX_NVN.zs"),
+            PathBuf::from("romfs/Pack/MW_Model.pack"),
+        ]
+    }
+
+    fn game_romfs() -> Vec<u8> {
+        romfs(&[
+            ("Model", &["MW_Field_plain.Nin_NX_NVN.zs"]),
+            ("Pack", &["MW_Model.pack"]),
+        ])
+    }
+
+    #[test]
+    fn lists_romfs_of_nsp() {
+        let dir = tempdir().unwrap();
+        let nsp = dir.path().join("game.nsp");
+        let files = [
+            (
+                "control.nca",
+                nca(2, &romfs(&[("Icon", &["icon.dat"])]), None),
+            ),
+            ("program.nca", nca(0, &game_romfs(), None)),
+        ];
+        fs::write(&nsp, partition(b"PFS0", PFS0_ENTRY_SIZE, &files)).unwrap();
+
+        let mut names = list_nsp(&nsp, &keys(dir.path())).unwrap().unwrap();
+        names.sort();
+        assert_eq!(names, expected_names());
+    }
+
+    #[test]
+    fn lists_romfs_of_xci_with_title_key() {
+        let dir = tempdir().unwrap();
+        let xci = dir.path().join("game.xci");
+        let secure = partition(
+            b"HFS0",
+            HFS0_ENTRY_SIZE,
+            &[
+                ("program.tik", ticket()),
+                ("program.nca", nca(0, &game_romfs(), Some(RIGHTS_ID))),
+            ],
+        );
+        let root = partition(b"HFS0", HFS0_ENTRY_SIZE, &[("secure", secure)]);
+        let mut data = vec![0; 0x1200];
+        data[0x1100..0x1104].copy_from_slice(b"HEAD");
+        data[0x1130..0x1138].copy_from_slice(&0x200u64.to_le_bytes());
+        data.extend(root);
+        fs::write(&xci, data).unwrap();
+
+        let mut names = list_xci(&xci, &keys(dir.path())).unwrap().unwrap();
+        names.sort();
+        assert_eq!(names, expected_names());
+    }
+
+    #[test]
+    fn lists_no_romfs_of_nsz() {
+        let dir = tempdir().unwrap();
+        let nsz = dir.path().join("game.nsz");
+        let files = [("program.ncz", nca(0, &game_romfs(), None))];
+        fs::write(&nsz, partition(b"PFS0", PFS0_ENTRY_SIZE, &files)).unwrap();
+        assert_eq!(list_nsp(&nsz, &keys(dir.path())).unwrap(), None);
+    }
+}
@@ -0,0 +1,227 @@
+use crate::{
+    error::{Error, ResultExt},
+    mods::{ModArchive, TarCompression},
+    Result,
+};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+const XCI_MAGIC: &[u8] = b"HEAD";
+/// Offsets of the card header in dumps without and with the initial key area.
+const XCI_MAGIC_OFFSETS: [usize; 2] = [0x100, 0x1100];
+const NSP_MAGIC: &[u8] = b"PFS0";
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+const SEVEN_Z_MAGIC: &[u8] = b"7z\xbc\xaf\x27\x1c";
+const RAR_MAGIC: &[u8] = b"Rar!\x1a\x07";
+const GZIP_MAGIC: &[u8] = b"\x1f\x8b";
+const ZSTD_MAGIC: &[u8] = b"\x28\xb5\x2f\xfd";
+const TAR_MAGIC: &[u8] = b"ustar";
+const TAR_MAGIC_OFFSET: usize = 257;
+const SNIFF_SIZE: usize = 0x1200;
+
+/// The kind of an input selected by the user.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputKind {
+    /// A dumped romfs or a LayeredFS mod directory.
+    Directory,
+    /// A game card dump, optionally with compressed NCZs (XCZ).
+    Xci,
+    /// An eShop dump, optionally with compressed NCZs (NSZ).
+    Nsp,
+    /// A mod archive.
+    Archive(ModArchive),
+}
+
+impl InputKind {
+    /// Detects the kind of an input by sniffing its magic bytes.
+    ///
+    /// Compressed files are assumed to contain a tar archive.
+    pub fn detect(path: &Path) -> Result<Self> {
+        if path.is_dir() {
+            return Ok(InputKind::Directory);
+        }
+
+        let mut magic = Vec::with_capacity(SNIFF_SIZE);
+        File::open(path)
+            .and_then(|file| file.take(SNIFF_SIZE as u64).read_to_end(&mut magic))
+            .with_path(path)?;
+
+        if magic.starts_with(NSP_MAGIC) {
+            Ok(InputKind::Nsp)
+        } else if XCI_MAGIC_OFFSETS
+            .iter()
+            .any(|offset| magic.get(*offset..*offset + XCI_MAGIC.len()) == Some(XCI_MAGIC))
+        {
+            Ok(InputKind::Xci)
+        } else if magic.starts_with(ZIP_MAGIC) {
+            Ok(InputKind::Archive(ModArchive::Zip))
+        } else if magic.starts_with(SEVEN_Z_MAGIC) {
+            Ok(InputKind::Archive(ModArchive::SevenZ))
+        } else if magic.starts_with(RAR_MAGIC) {
+            Ok(InputKind::Archive(ModArchive::Rar))
+        } else if magic.starts_with(GZIP_MAGIC) {
+            Ok(InputKind::Archive(ModArchive::Tar(TarCompression::Gzip)))
+        } else if magic.starts_with(ZSTD_MAGIC) {
+            Ok(InputKind::Archive(ModArchive::Tar(TarCompression::Zstd)))
+        } else if magic.get(TAR_MAGIC_OFFSET..TAR_MAGIC_OFFSET + TAR_MAGIC.len()) == Some(TAR_MAGIC)
+        {
+            Ok(InputKind::Archive(ModArchive::Tar(TarCompression::None)))
+        } else {
+            Err(Error::FileExtensionUnsupported)
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            InputKind::Directory => "folder",
+            InputKind::Xci => "XCI",
+            InputKind::Nsp => "NSP",
+            InputKind::Archive(ModArchive::Zip) => "zip archive",
+            InputKind::Archive(ModArchive::SevenZ) => "7z archive",
+            InputKind::Archive(ModArchive::Tar(TarCompression::None)) => "tar archive",
+            InputKind::Archive(ModArchive::Tar(TarCompression::Gzip)) => "tar.gz archive",
+            InputKind::Archive(ModArchive::Tar(TarCompression::Zstd)) => "tar.zst archive",
+            InputKind::Archive(ModArchive::Rar) => "RAR archive",
+        }
+    }
+
+    /// Describes how the input is prepared for bundling.
+    ///
+    /// Folders and zip archives are read in place, everything else is extracted first.
+    pub fn step(&self) -> String {
+        match self {
+            InputKind::Directory | InputKind::Archive(ModArchive::Zip) => {
+                format!("Reading {}...", self.name())
+            }
+            _ => format!("Extracting {}...", self.name()),
+        }
+    }
+
+    /// Whether this input is an original game dump, which requires prod keys.
+    pub fn is_game_dump(&self) -> bool {
+        matches!(self, InputKind::Xci | InputKind::Nsp)
+    }
+
+    /// How often the whole input is decrypted by hactool during its extraction.
+    pub fn decrypt_passes(&self) -> u64 {
+        match self {
+            InputKind::Xci => 2,
+            InputKind::Nsp => 1,
+            _ => 0,
+        }
+    }
+}
+
+/// Whether an input is the base game, which is the lowest layer and which mods are compared
+/// with.
+///
+/// Besides game dumps, directories containing all required files are a dumped romfs of the
+/// base game, since mods only replace some of them.
+pub fn is_base_game(
+    kind: InputKind,
+    content: Option<&Vec<String>>,
+    required_files: &[String],
+) -> bool {
+    match kind {
+        InputKind::Xci | InputKind::Nsp => true,
+        InputKind::Directory => content.map_or(false, |content| {
+            required_files
+                .iter()
+                .all(|required_file| content.contains(required_file))
+        }),
+        InputKind::Archive(_) => false,
+    }
+}
+
+/// Detects the kinds of the given files and sorts them into layer order.
+///
+/// Returns the inputs together with the number of base game inputs, which come first.
+pub fn detect_inputs(
+    files: &[PathBuf],
+    file_content: &HashMap<PathBuf, Vec<String>>,
+    required_files: &[String],
+) -> Result<(Vec<(PathBuf, InputKind)>, usize)> {
+    let mut inputs = files
+        .iter()
+        .map(|file| {
+            let kind = InputKind::detect(file)?;
+            let base = is_base_game(kind, file_content.get(file), required_files);
+            Ok((file.clone(), kind, base))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    // the base game is always the lowest layer, mods are stacked on top in the selected order
+    inputs.sort_by_key(|(_, _, base)| !base);
+    let base_count = inputs.iter().filter(|(_, _, base)| *base).count();
+    let inputs = inputs
+        .into_iter()
+        .map(|(file, kind, _)| (file, kind))
+        .collect();
+    Ok((inputs, base_count))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn detect(data: &[u8]) -> Result<InputKind> {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("input");
+        fs::write(&path, data).unwrap();
+        InputKind::detect(&path)
+    }
+
+    #[test]
+    fn detects_xci_with_and_without_key_area() {
+        for offset in XCI_MAGIC_OFFSETS {
+            let mut data = vec![0; offset + 0x200];
+            data[offset..offset + XCI_MAGIC.len()].copy_from_slice(XCI_MAGIC);
+            assert_eq!(detect(&data).unwrap(), InputKind::Xci);
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_files() {
+        assert!(matches!(
+            detect(&[0; 0x2000]),
+            Err(Error::FileExtensionUnsupported)
+        ));
+    }
+
+    #[test]
+    fn sorts_base_games_before_mods_in_selected_order() {
+        let dir = tempdir().unwrap();
+        let required_files = vec!["romfs/Model/MW_Field_plain.Nin_NX_NVN.zs".to_string()];
+        let mod_dir = dir.path().join("mod");
+        let dump_dir = dir.path().join("dump");
+        let xci = dir.path().join("game.xci");
+        let mut data = vec![0; 0x200];
+        data[0x100..0x104].copy_from_slice(XCI_MAGIC);
+        fs::write(&xci, data).unwrap();
+        fs::create_dir(&mod_dir).unwrap();
+        fs::create_dir(&dump_dir).unwrap();
+        let mut file_content = HashMap::new();
+        file_content.insert(
+            mod_dir.clone(),
+            vec!["romfs/Model/MW_Model.pack".to_string()],
+        );
+        file_content.insert(dump_dir.clone(), required_files.clone());
+
+        let files = [mod_dir.clone(), dump_dir.clone(), xci.clone()];
+        let (inputs, base_count) = detect_inputs(&files, &file_content, &required_files).unwrap();
+        assert_eq!(
+            inputs,
+            vec![
+                (dump_dir, InputKind::Directory),
+                (xci, InputKind::Xci),
+                (mod_dir, InputKind::Directory),
+            ]
+        );
+        assert_eq!(base_count, 2);
+    }
+}
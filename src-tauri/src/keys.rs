@@ -1,69 +1,182 @@
-use crate::Result;
-use std::{collections::HashSet, path::PathBuf};
+use crate::{
+    error::{Error, ResultExt},
+    Result,
+};
+use glob::{glob, Pattern};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    env, fs,
+    path::{Path, PathBuf},
+};
 
-pub fn find_keys() -> Result<Vec<PathBuf>> {
-    let mut found_keys: HashSet<PathBuf> = HashSet::new();
-    let yuzu_guesses = ["yuzu", "yuzu-emu"];
-    let ryujinx_guesses = ["Ryujinx"];
+const KEY_FILE_NAME: &str = "prod.keys";
+
+/// Emulators that store their keys in a `keys` directory within their user directory.
+const YUZU_LIKE_GUESSES: [(&str, &str); 8] = [
+    ("yuzu", "yuzu"),
+    ("yuzu-emu", "yuzu"),
+    ("suyu", "suyu"),
+    ("sudachi", "Sudachi"),
+    ("citron", "Citron"),
+    ("torzu", "torzu"),
+    ("eden", "Eden"),
+    ("eden-emu", "Eden"),
+];
+
+/// Emulators that store their keys in a `system` directory within their user directory.
+const RYUJINX_LIKE_GUESSES: [(&str, &str); 2] = [("Ryujinx", "Ryujinx"), ("Ryubing", "Ryubing")];
+
+#[derive(Clone, Debug, Serialize)]
+pub struct FoundKey {
+    pub path: PathBuf,
+    pub source: String,
+}
+
+pub fn find_keys() -> Result<Vec<FoundKey>> {
+    let mut found_keys: Vec<FoundKey> = vec![];
+
+    let mut base_dirs: Vec<(PathBuf, &str)> = vec![];
     if let Some(data_dir) = dirs::data_dir() {
-        find_key(
-            &mut found_keys,
-            data_dir.clone(),
-            &yuzu_guesses,
-            PathBuf::from("keys"),
-        )?;
-        find_key(
-            &mut found_keys,
-            data_dir,
-            &ryujinx_guesses,
-            PathBuf::from("system"),
-        )?;
+        base_dirs.push((data_dir, "data dir"));
     }
     if let Some(config_dir) = dirs::config_dir() {
-        find_key(
-            &mut found_keys,
-            config_dir.clone(),
-            &yuzu_guesses,
-            PathBuf::from("keys"),
-        )?;
-        find_key(
-            &mut found_keys,
-            config_dir,
-            &ryujinx_guesses,
-            PathBuf::from("system"),
-        )?;
+        base_dirs.push((config_dir, "config dir"));
     }
     if let Some(data_local_dir) = dirs::data_local_dir() {
+        base_dirs.push((data_local_dir, "local data dir"));
+    }
+    if let Some(dir) = env::var_os("XDG_DATA_HOME") {
+        base_dirs.push((PathBuf::from(dir), "XDG_DATA_HOME"));
+    }
+    if let Some(dir) = env::var_os("XDG_CONFIG_HOME") {
+        base_dirs.push((PathBuf::from(dir), "XDG_CONFIG_HOME"));
+    }
+    if let Some(home_dir) = dirs::home_dir() {
+        for pattern in ["data", "config"] {
+            let apps_dir = Pattern::escape(&home_dir.join(".var").join("app").to_string_lossy());
+            let flatpak_dirs = Path::new(&apps_dir).join("*").join(pattern);
+            // unreadable app directories cannot contain keys
+            for entry in glob(&flatpak_dirs.to_string_lossy())?.flatten() {
+                base_dirs.push((entry, "Flatpak"));
+            }
+        }
+    }
+    for (dir, location) in base_dirs.into_iter() {
+        find_key(&mut found_keys, &dir, &YUZU_LIKE_GUESSES, "keys", location);
         find_key(
             &mut found_keys,
-            data_local_dir.clone(),
-            &yuzu_guesses,
-            PathBuf::from("keys"),
-        )?;
-        find_key(
+            &dir,
+            &RYUJINX_LIKE_GUESSES,
+            "system",
+            location,
+        );
+    }
+
+    for dir in portable_dirs() {
+        add_key(
+            &mut found_keys,
+            dir.join("user").join("keys").join(KEY_FILE_NAME),
+            "Portable yuzu-like emulator",
+        );
+        add_key(
             &mut found_keys,
-            data_local_dir,
-            &ryujinx_guesses,
-            PathBuf::from("system"),
-        )?;
+            dir.join("portable").join("system").join(KEY_FILE_NAME),
+            "Portable Ryujinx",
+        );
     }
-    Ok(found_keys.into_iter().collect())
+
+    if let Some(home_dir) = dirs::home_dir() {
+        add_key(
+            &mut found_keys,
+            home_dir.join(".switch").join(KEY_FILE_NAME),
+            "hactool",
+        );
+    }
+
+    Ok(found_keys)
 }
 
-fn find_key(
-    found_keys: &mut HashSet<PathBuf>,
-    dir: PathBuf,
-    guesses: &[&str],
-    key_path: PathBuf,
-) -> Result<()> {
-    for guess in guesses.iter() {
-        let current_dir = dir.join(guess);
-        if current_dir.exists() && current_dir.join(key_path.as_path()).exists() {
-            let key = current_dir.join(key_path.as_path()).join("prod.keys");
-            if key.exists() {
-                found_keys.insert(key);
-            }
+/// Directories in which a portable emulator installation might live.
+///
+/// Besides the install directories of the yuzu installer, portable emulators are unpacked
+/// anywhere, so the directories of this executable and the current working directory are
+/// checked together with their subdirectories, in case an emulator has been unpacked there.
+fn portable_dirs() -> Vec<PathBuf> {
+    let mut dirs = vec![];
+    if let Some(data_local_dir) = dirs::data_local_dir() {
+        for install_dir in ["yuzu-windows-msvc", "yuzu-windows-msvc-early-access"] {
+            dirs.push(data_local_dir.join("yuzu").join(install_dir));
         }
     }
-    Ok(())
+
+    let mut parent_dirs = vec![];
+    if let Some(exe_dir) = env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(Path::to_path_buf))
+    {
+        parent_dirs.push(exe_dir);
+    }
+    if let Ok(current_dir) = env::current_dir() {
+        parent_dirs.push(current_dir);
+    }
+    for parent_dir in parent_dirs {
+        let sub_dirs = fs::read_dir(&parent_dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|dir_entry| dir_entry.path())
+            .filter(|path| path.is_dir());
+        dirs.extend(sub_dirs);
+        dirs.push(parent_dir);
+    }
+    dirs
+}
+
+fn find_key(
+    found_keys: &mut Vec<FoundKey>,
+    dir: &Path,
+    guesses: &[(&str, &str)],
+    key_path: &str,
+    location: &str,
+) {
+    for (guess, emulator) in guesses.iter() {
+        let key = dir.join(guess).join(key_path).join(KEY_FILE_NAME);
+        add_key(found_keys, key, &format!("{} ({})", emulator, location));
+    }
+}
+
+fn add_key(found_keys: &mut Vec<FoundKey>, key: PathBuf, source: &str) {
+    if key.is_file() && !found_keys.iter().any(|found_key| found_key.path == key) {
+        found_keys.push(FoundKey {
+            path: key,
+            source: source.to_string(),
+        });
+    }
+}
+
+/// Keys parsed from a `prod.keys` file.
+pub struct Keys(HashMap<String, Vec<u8>>);
+
+impl Keys {
+    pub fn read(prod_key: &Path) -> Result<Self> {
+        let content = fs::read_to_string(prod_key).with_path(prod_key)?;
+        let keys = content
+            .lines()
+            .filter_map(|line| line.split_once('='))
+            .filter_map(|(name, value)| {
+                hex::decode(value.trim())
+                    .ok()
+                    .map(|value| (name.trim().to_lowercase(), value))
+            })
+            .collect();
+        Ok(Self(keys))
+    }
+
+    pub fn get(&self, name: &str) -> Result<Vec<u8>> {
+        self.0
+            .get(name)
+            .cloned()
+            .ok_or_else(|| Error::KeyMissing(name.to_string()))
+    }
 }
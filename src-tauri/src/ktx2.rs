@@ -0,0 +1,100 @@
+//! Minimal KTX2 writer for single level RGBA8 textures.
+//!
+//! Basis Universal transcodable textures would require the C++ Basis encoder, so textures
+//! are stored as `VK_FORMAT_R8G8B8A8_SRGB` instead and supercompressed with Zstandard.
+
+use crate::Result;
+use std::io::{self, Write};
+
+const IDENTIFIER: [u8; 12] = [
+    0xab, 0x4b, 0x54, 0x58, 0x20, 0x32, 0x30, 0xbb, 0x0d, 0x0a, 0x1a, 0x0a,
+];
+const VK_FORMAT_R8G8B8A8_SRGB: u32 = 43;
+const SUPERCOMPRESSION_ZSTD: u32 = 2;
+const ZSTD_LEVEL: i32 = 19;
+
+/// Size of the identifier, the header and the index.
+const HEADER_LENGTH: u32 = 80;
+/// Size of the level index with a single level.
+const LEVEL_INDEX_LENGTH: u32 = 24;
+
+const KHR_DF_MODEL_RGBSDA: u8 = 1;
+const KHR_DF_PRIMARIES_BT709: u8 = 1;
+const KHR_DF_TRANSFER_SRGB: u8 = 2;
+const KHR_DF_CHANNEL_RGBSDA_ALPHA: u8 = 15;
+const KHR_DF_SAMPLE_DATATYPE_LINEAR: u8 = 1 << 4;
+
+pub fn write_rgba8<W: Write>(writer: &mut W, data: &[u8], width: u32, height: u32) -> Result<()> {
+    if data.len() != width as usize * height as usize * 4 {
+        return Err(io::Error::from(io::ErrorKind::InvalidInput).into());
+    }
+    let level_data = zstd::bulk::compress(data, ZSTD_LEVEL)?;
+    let dfd = data_format_descriptor();
+    let dfd_offset = HEADER_LENGTH + LEVEL_INDEX_LENGTH;
+    let level_offset = dfd_offset + dfd.len() as u32;
+
+    writer.write_all(&IDENTIFIER)?;
+    for value in [
+        VK_FORMAT_R8G8B8A8_SRGB,
+        1, // type size
+        width,
+        height,
+        0, // pixel depth
+        0, // layer count
+        1, // face count
+        1, // level count
+        SUPERCOMPRESSION_ZSTD,
+        dfd_offset,
+        dfd.len() as u32,
+        0, // key/value data offset
+        0, // key/value data length
+    ] {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    for value in [
+        0, // supercompression global data offset
+        0, // supercompression global data length
+        level_offset as u64,
+        level_data.len() as u64,
+        data.len() as u64,
+    ] {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    writer.write_all(&dfd)?;
+    writer.write_all(&level_data)?;
+    Ok(())
+}
+
+/// Describes sRGB encoded RGBA8 data with straight alpha.
+fn data_format_descriptor() -> Vec<u8> {
+    let block_size: u16 = 24 + 16 * 4;
+    let mut dfd = vec![];
+    dfd.extend_from_slice(&(4 + block_size as u32).to_le_bytes());
+    dfd.extend_from_slice(&0u32.to_le_bytes()); // vendor id and descriptor type
+    dfd.extend_from_slice(&2u16.to_le_bytes()); // version
+    dfd.extend_from_slice(&block_size.to_le_bytes());
+    dfd.extend_from_slice(&[
+        KHR_DF_MODEL_RGBSDA,
+        KHR_DF_PRIMARIES_BT709,
+        KHR_DF_TRANSFER_SRGB,
+        0, // straight alpha
+    ]);
+    // texel block dimensions
+    dfd.extend_from_slice(&[0; 4]);
+    // bytes per plane must be zero for supercompressed data
+    dfd.extend_from_slice(&[0; 8]);
+    let channels = [
+        0, // red
+        1, // green
+        2, // blue
+        KHR_DF_CHANNEL_RGBSDA_ALPHA | KHR_DF_SAMPLE_DATATYPE_LINEAR,
+    ];
+    for (index, channel) in channels.iter().enumerate() {
+        dfd.extend_from_slice(&(index as u16 * 8).to_le_bytes()); // bit offset
+        dfd.extend_from_slice(&[7, *channel]); // bit length - 1 and channel
+        dfd.extend_from_slice(&[0; 4]); // sample position
+        dfd.extend_from_slice(&0u32.to_le_bytes()); // lower
+        dfd.extend_from_slice(&255u32.to_le_bytes()); // upper
+    }
+    dfd
+}
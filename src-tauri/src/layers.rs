@@ -0,0 +1,206 @@
+use crate::{bundle::find_romfs_dir_in_names, error::ResultExt, Result};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fs::{self, read_dir, File},
+    io::Read,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+use zip::ZipArchive;
+
+/// Directories of the romfs, which contain assets.
+const ASSET_DIRS: [&str; 2] = ["Model", "Pack"];
+
+/// Where the romfs of a layer is located.
+pub enum LayerSource {
+    /// An extracted or dumped romfs directory.
+    Dir(PathBuf),
+    /// The romfs directory within a zip archive, which is read without extracting it.
+    Zip(ZipLayer),
+}
+
+/// A zip archive, which is opened once and indexed by asset, so that assets can be read
+/// without scanning the archive again.
+pub struct ZipLayer {
+    archive: Mutex<ZipArchive<File>>,
+    /// Entry index of each asset.
+    entries: BTreeMap<PathBuf, usize>,
+}
+
+/// The romfs of a single input.
+pub struct Layer {
+    pub input: PathBuf,
+    pub source: LayerSource,
+}
+
+impl Layer {
+    pub fn dir(input: &Path, romfs_dir: PathBuf) -> Self {
+        Self {
+            input: input.to_path_buf(),
+            source: LayerSource::Dir(romfs_dir),
+        }
+    }
+
+    pub fn zip(input: &Path) -> Result<Self> {
+        let mut archive = ZipArchive::new(File::open(input).with_path(input)?).with_path(input)?;
+        let romfs_dir =
+            find_romfs_dir_in_names(archive.file_names().map(Path::new)).with_path(input)?;
+        let mut entries = BTreeMap::new();
+        for i in 0..archive.len() {
+            let entry = archive.by_index(i).with_path(input)?;
+            if let Some(asset) = entry
+                .enclosed_name()
+                .and_then(|name| zip_asset(&romfs_dir, name))
+            {
+                if entry.is_file() {
+                    entries.insert(asset, i);
+                }
+            }
+        }
+        Ok(Self {
+            input: input.to_path_buf(),
+            source: LayerSource::Zip(ZipLayer {
+                archive: Mutex::new(archive),
+                entries,
+            }),
+        })
+    }
+
+    /// Reads an asset of this layer, if it exists.
+    pub fn read(&self, asset: &Path) -> Result<Option<Vec<u8>>> {
+        match &self.source {
+            LayerSource::Dir(romfs_dir) => {
+                let path = romfs_dir.join(asset);
+                if path.is_file() {
+                    Ok(Some(fs::read(&path).with_path(&path)?))
+                } else {
+                    Ok(None)
+                }
+            }
+            LayerSource::Zip(zip) => {
+                let index = match zip.entries.get(asset) {
+                    Some(index) => *index,
+                    None => return Ok(None),
+                };
+                // entries are decompressed one at a time, since they share the archive
+                let mut archive = zip.archive.lock().unwrap();
+                let mut entry = archive.by_index(index).with_path(&self.input)?;
+                let mut data = Vec::with_capacity(entry.size() as usize);
+                entry.read_to_end(&mut data).with_path(&self.input)?;
+                Ok(Some(data))
+            }
+        }
+    }
+
+    /// Lists the assets of this layer relative to its romfs directory.
+    pub fn assets(&self) -> Result<Vec<PathBuf>> {
+        let mut assets = vec![];
+        match &self.source {
+            LayerSource::Dir(romfs_dir) => {
+                for asset_dir in ASSET_DIRS.iter() {
+                    for dir_entry in read_dir(romfs_dir.join(asset_dir)).into_iter().flatten() {
+                        let dir_entry = dir_entry?;
+                        if dir_entry.file_type()?.is_file() {
+                            assets.push(Path::new(asset_dir).join(dir_entry.file_name()));
+                        }
+                    }
+                }
+            }
+            LayerSource::Zip(zip) => assets.extend(zip.entries.keys().cloned()),
+        }
+        Ok(assets)
+    }
+}
+
+/// Returns the asset path relative to the romfs directory, if the given zip entry is an asset.
+fn zip_asset(romfs_dir: &Path, name: &Path) -> Option<PathBuf> {
+    let asset = name.strip_prefix(romfs_dir).ok()?;
+    let asset_dir = asset.parent()?;
+    if ASSET_DIRS.iter().any(|dir| asset_dir == Path::new(dir)) {
+        Some(asset.to_path_buf())
+    } else {
+        None
+    }
+}
+
+/// An asset of a lower layer, which has been overridden by a higher layer.
+#[derive(Clone, Debug, Serialize)]
+pub struct Conflict {
+    pub asset: PathBuf,
+    pub overridden: PathBuf,
+    pub by: PathBuf,
+}
+
+/// Virtual romfs, which stacks all layers on top of each other.
+///
+/// If multiple layers contain the same asset, the asset of the highest layer wins.
+pub struct LayeredFs {
+    pub layers: Vec<Layer>,
+    assets: BTreeMap<PathBuf, usize>,
+    pub conflicts: Vec<Conflict>,
+}
+
+impl LayeredFs {
+    pub fn new(layers: Vec<Layer>) -> Result<Self> {
+        let mut assets = BTreeMap::new();
+        let mut conflicts = vec![];
+        for (index, layer) in layers.iter().enumerate() {
+            for asset in layer.assets()? {
+                if let Some(overridden) = assets.insert(asset.clone(), index) {
+                    conflicts.push(Conflict {
+                        asset,
+                        overridden: layers[overridden].input.clone(),
+                        by: layer.input.clone(),
+                    });
+                }
+            }
+        }
+        Ok(Self {
+            layers,
+            assets,
+            conflicts,
+        })
+    }
+
+    pub fn asset_count(&self) -> usize {
+        self.assets.len()
+    }
+
+    /// Lists all assets in order together with the layer providing them.
+    pub fn assets(&self) -> impl Iterator<Item = (&Path, &Layer)> {
+        self.assets
+            .iter()
+            .map(move |(asset, index)| (asset.as_path(), &self.layers[*index]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::tempdir;
+    use zip::{write::FileOptions, ZipWriter};
+
+    #[test]
+    fn finds_romfs_of_zip_without_dir_entries() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("mod.zip");
+        let mut writer = ZipWriter::new(File::create(&path).unwrap());
+        writer
+            .start_file("mod/romfs/Model/MW_Field_plain.zs", FileOptions::default())
+            .unwrap();
+        writer.write_all(b"data").unwrap();
+        writer.finish().unwrap();
+
+        let layer = Layer::zip(&path).unwrap();
+        assert_eq!(
+            layer.assets().unwrap(),
+            vec![PathBuf::from("Model/MW_Field_plain.zs")]
+        );
+        assert_eq!(
+            layer.read(Path::new("Model/MW_Field_plain.zs")).unwrap(),
+            Some(b"data".to_vec())
+        );
+    }
+}
@@ -0,0 +1,10 @@
+//! Texture extraction, image encoding and tar writing, which are shared by the app and its
+//! benchmarks.
+
+pub mod encoding;
+pub mod error;
+mod ktx2;
+pub mod ninres;
+pub mod tar_writer;
+
+pub type Result<T> = std::result::Result<T, error::Error>;
@@ -4,42 +4,89 @@
 )]
 
 mod bundle;
-mod error;
+mod cache;
+mod coverage;
+mod diff;
+mod game_dump;
+mod input;
 mod keys;
+mod layers;
+mod manifest;
 mod mods;
-mod ninres;
+mod nca;
+mod ncz;
+mod output;
+mod progress;
+mod romfs;
+mod run_log;
+mod settings;
+mod sidecar;
+mod ticket;
 mod xci;
 
-pub type Result<T> = std::result::Result<T, error::Error>;
-
-use bundle::{bundle_assets, find_romfs_dir, find_romfs_dir_in_zip_archive, finish_bundle_assets};
-use error::Error;
-use glob::glob;
+use app_lib::{encoding, error, ninres, tar_writer, Result};
+use bundle::{
+    bundle_layers, find_romfs_dir, find_romfs_dir_in_names, finish_bundle_assets, list_dir,
+};
+use cache::{file_key, Cache};
+use coverage::Coverage;
+use diff::{diff_mod, ModDiff};
+use encoding::{AssetRule, AssetRules};
+use error::{Error, ResultExt};
+use game_dump::{list_nsp, list_xci};
+use input::{detect_inputs, InputKind};
 use itertools::Itertools;
-use mods::{extract_7z, extract_zip};
+use keys::Keys;
+use layers::{Conflict, Layer, LayerSource, LayeredFs};
+use manifest::BaseBundle;
+use mods::{read_tar, ModArchive};
 use nfd2::Response;
-use pathdiff::diff_paths;
+use output::OutputFormat;
+use progress::{Phase, Progress};
+use run_log::{format_log, LogEntries, LogEntry, LogLevel, RunLog};
+use settings::Settings;
 use std::{
     collections::HashMap,
-    env,
-    ffi::OsStr,
-    fs::{self, File},
-    io,
+    env, fs,
     path::{Path, PathBuf},
-    sync::{Arc, Mutex, RwLock},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, RwLock,
+    },
 };
+use tar_writer::TarWriter;
 use tauri::{State, Window};
-use tempfile::tempdir;
-use xci::extract_xci;
+use tempfile::{tempdir, TempDir};
+use xci::{extract_nsp, extract_xci};
+
+/// Maximum number of bundled asset files, whose entries wait to be written.
+const BUNDLE_QUEUE_CAPACITY: usize = 64;
+
+/// Weights of the phases of an extraction, which roughly match their share of the run time.
+const EXTRACT_PHASES: [(Phase, f64); 4] = [
+    (Phase::Decrypt, 0.5),
+    (Phase::Bundle, 0.15),
+    (Phase::Encode, 0.3),
+    (Phase::Finish, 0.05),
+];
+const DIFF_PHASES: [(Phase, f64); 2] = [(Phase::Decrypt, 0.7), (Phase::Compare, 0.3)];
 
 struct AppState {
-    keys: RwLock<Vec<PathBuf>>,
+    keys: RwLock<Vec<keys::FoundKey>>,
     prod_key: RwLock<Option<PathBuf>>,
+    output_dir: RwLock<Option<PathBuf>>,
+    output_format: RwLock<OutputFormat>,
+    asset_rules: RwLock<Vec<AssetRule>>,
+    optimize_png: RwLock<bool>,
     selected_files: Arc<RwLock<Vec<PathBuf>>>,
     bundle_data: RwLock<Option<Vec<u8>>>,
+    base_bundle: RwLock<Option<(PathBuf, Vec<u8>)>>,
     file_content: RwLock<HashMap<PathBuf, Vec<String>>>,
     required_files: RwLock<Vec<String>>,
-    has_original_game_files: RwLock<bool>,
+    /// Log of the current or last extraction or comparison.
+    log: LogEntries,
+    /// Whether the log view shows debug entries, which are only streamed then.
+    log_details: Arc<AtomicBool>,
 }
 
 fn main() {
@@ -47,8 +94,13 @@ fn main() {
         .manage(AppState {
             keys: RwLock::new(vec![]),
             prod_key: RwLock::new(None),
+            output_dir: RwLock::new(None),
+            output_format: RwLock::new(OutputFormat::default()),
+            asset_rules: RwLock::new(vec![]),
+            optimize_png: RwLock::new(false),
             selected_files: Arc::new(RwLock::new(vec![])),
             bundle_data: RwLock::new(None),
+            base_bundle: RwLock::new(None),
             file_content: RwLock::new(HashMap::new()),
             required_files: RwLock::new(vec![
                 "romfs/Pack/MW_Model.pack".to_string(),
@@ -71,32 +123,97 @@ fn main() {
                 "romfs/Model/MW_Field_airship.Nin_NX_NVN.zs".to_string(),
                 "romfs/Model/MW_DV_airship_V.Nin_NX_NVN.zs".to_string(),
             ]),
-            has_original_game_files: RwLock::new(false),
+            log: Arc::new(Mutex::new(vec![])),
+            log_details: Arc::new(AtomicBool::new(false)),
         })
         .invoke_handler(tauri::generate_handler![
             find_keys,
+            load_settings,
+            reset_settings,
             set_prod_key,
             select_prod_key,
             add_files,
             assert_added_files,
+            get_coverage,
             remove_file,
+            move_file_up,
             extract_assets,
-            save_bundle_data
+            diff_mods,
+            set_output_format,
+            set_asset_rules,
+            set_optimize_png,
+            save_bundle_data,
+            load_base_bundle,
+            remove_base_bundle,
+            clear_cache,
+            set_log_details,
+            export_log
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
 
 #[tauri::command]
-fn find_keys(state: State<AppState>) -> Result<Vec<PathBuf>> {
+fn find_keys(state: State<AppState>) -> Result<Vec<keys::FoundKey>> {
     let keys = keys::find_keys()?;
     *state.keys.write().unwrap() = keys.clone();
     Ok(keys)
 }
 
+/// Restores the settings and the file selection of the last session.
+///
+/// Selected files, which do not exist anymore, are dropped.
+#[tauri::command]
+async fn load_settings(state: State<'_, AppState>) -> Result<Settings> {
+    let mut settings = Settings::load()?;
+    settings.prod_key = settings.prod_key.filter(|prod_key| prod_key.is_file());
+    *state.prod_key.write().unwrap() = settings.prod_key.clone();
+    *state.output_dir.write().unwrap() = settings.output_dir.clone();
+    *state.output_format.write().unwrap() = settings.output_format;
+    *state.asset_rules.write().unwrap() = settings.asset_rules.clone();
+    *state.optimize_png.write().unwrap() = settings.optimize_png;
+
+    state.selected_files.write().unwrap().clear();
+    state.file_content.write().unwrap().clear();
+    for file in settings.selected_files.iter() {
+        if file.exists() && check_added_file(&state, file).is_ok() {
+            state.selected_files.write().unwrap().push(file.clone());
+        }
+    }
+    settings.selected_files = state.selected_files.read().unwrap().clone();
+    Ok(settings)
+}
+
+#[tauri::command]
+fn reset_settings(state: State<AppState>) -> Result<()> {
+    Settings::reset()?;
+    *state.prod_key.write().unwrap() = None;
+    *state.output_dir.write().unwrap() = None;
+    *state.output_format.write().unwrap() = OutputFormat::default();
+    state.asset_rules.write().unwrap().clear();
+    *state.optimize_png.write().unwrap() = false;
+    state.selected_files.write().unwrap().clear();
+    state.file_content.write().unwrap().clear();
+    Ok(())
+}
+
+fn save_settings(state: &State<AppState>) -> Result<()> {
+    Settings {
+        prod_key: state.prod_key.read().unwrap().clone(),
+        output_dir: state.output_dir.read().unwrap().clone(),
+        output_format: *state.output_format.read().unwrap(),
+        selected_files: state.selected_files.read().unwrap().clone(),
+        asset_rules: state.asset_rules.read().unwrap().clone(),
+        optimize_png: *state.optimize_png.read().unwrap(),
+    }
+    .save()
+}
+
 #[tauri::command]
-fn set_prod_key(prod_key: PathBuf, state: State<AppState>) {
+fn set_prod_key(prod_key: PathBuf, state: State<AppState>) -> Result<()> {
     *state.prod_key.write().unwrap() = Some(prod_key);
+    relist_game_dumps(&state);
+    save_settings(&state)
 }
 
 #[tauri::command]
@@ -105,8 +222,10 @@ fn select_prod_key(state: State<AppState>) -> Result<PathBuf> {
 
     match result {
         Response::Okay(file_path) => {
-            *state.prod_key.write().unwrap() = Some(file_path);
-            Ok(state.prod_key.read().unwrap().clone().unwrap())
+            *state.prod_key.write().unwrap() = Some(file_path.clone());
+            relist_game_dumps(&state);
+            save_settings(&state)?;
+            Ok(file_path)
         }
         Response::OkayMultiple(_) => {
             unreachable!();
@@ -116,17 +235,13 @@ fn select_prod_key(state: State<AppState>) -> Result<PathBuf> {
 }
 
 #[tauri::command]
-async fn add_files(
-    files: Vec<PathBuf>,
-    state: State<'_, AppState>,
-    window: Window,
-) -> Result<Vec<PathBuf>> {
+async fn add_files(files: Vec<PathBuf>, state: State<'_, AppState>) -> Result<Vec<PathBuf>> {
     for file in files {
-        let window = window.clone();
-        check_added_file(&state, &file, window).await?;
+        check_added_file(&state, &file)?;
         state.selected_files.write().unwrap().push(file);
     }
     dedup_files(&state);
+    save_settings(&state)?;
     Ok(state.selected_files.read().unwrap().clone())
 }
 
@@ -142,194 +257,203 @@ fn dedup_files(state: &State<AppState>) {
     *state.selected_files.write().unwrap() = files;
 }
 
-async fn check_added_file(state: &State<'_, AppState>, file: &Path, window: Window) -> Result<()> {
-    let file_name = Path::new(file);
-    let extension = file_name.extension().and_then(OsStr::to_str);
-    if extension == Some("zip") {
-        let file = File::open(file).unwrap();
-
-        let mut archive = zip::ZipArchive::new(file).unwrap();
-        let romfs_dir = find_romfs_dir_in_zip_archive(&mut archive)?;
-
-        let mut file_content = vec![];
-        for i in 0..archive.len() {
-            let file = archive.by_index(i).unwrap();
-            if let Some(name) = file.enclosed_name() {
-                let mut parent_dir = romfs_dir.clone();
-                parent_dir.pop();
-                if let Some(path_diff) = diff_paths(&name, parent_dir) {
-                    let name = path_diff.to_string_lossy().to_string();
-                    if state.required_files.read().unwrap().contains(&name) {
-                        file_content.push(name);
-                    }
-                }
-            }
+fn check_added_file(state: &AppState, file: &Path) -> Result<()> {
+    match InputKind::detect(file)? {
+        InputKind::Directory => {
+            let romfs_dir = find_romfs_dir(file)?;
+            let names = list_dir(&romfs_dir)?;
+            add_file_content(state, file, &romfs_dir, &names);
         }
-        state
-            .file_content
-            .write()
-            .unwrap()
-            .insert(file_name.to_path_buf(), file_content);
-    } else if extension == Some("7z") {
-        let dir = tempdir()?;
-        extract_7z(window, &dir, file).await?;
-
-        let mut file_content = vec![];
-        let romfs_dir = find_romfs_dir(&dir)?;
-        for entry in glob(&format!("{}/**/*", dir.path().display())).unwrap() {
-            let entry = entry?;
-            let mut parent_dir = romfs_dir.clone();
-            parent_dir.pop();
-            if let Some(path_diff) = diff_paths(&entry, parent_dir) {
-                let name = path_diff.to_string_lossy().to_string();
-                if state.required_files.read().unwrap().contains(&name) {
-                    file_content.push(name);
+        InputKind::Archive(archive) => {
+            let names = archive.list(file)?;
+            let romfs_dir =
+                find_romfs_dir_in_names(names.iter().map(PathBuf::as_path)).with_path(file)?;
+            add_file_content(state, file, &romfs_dir, &names);
+        }
+        // listing game dumps requires decrypting them, so without keys their content is only
+        // known once they have been extracted
+        kind @ (InputKind::Xci | InputKind::Nsp) => {
+            let prod_key = state.prod_key.read().unwrap().clone();
+            if let Some(prod_key) = prod_key {
+                let keys = Keys::read(&prod_key)?;
+                let names = if kind == InputKind::Xci {
+                    list_xci(file, &keys)?
+                } else {
+                    list_nsp(file, &keys)?
+                };
+                if let Some(names) = names {
+                    add_file_content(state, file, Path::new("romfs"), &names);
                 }
             }
         }
-        state
-            .file_content
-            .write()
-            .unwrap()
-            .insert(file_name.to_path_buf(), file_content);
-    } else if extension == Some("xci") || extension == Some("nsp") {
-        *state.has_original_game_files.write().unwrap() = true;
     }
     Ok(())
 }
 
+/// Lists the selected game dumps again, since their content depends on the prod keys.
+fn relist_game_dumps(state: &AppState) {
+    let files = state.selected_files.read().unwrap().clone();
+    for file in files.iter() {
+        if InputKind::detect(file).map_or(false, |kind| kind.is_game_dump()) {
+            state.file_content.write().unwrap().remove(file);
+            // dumps, which cannot be listed, are listed after their extraction instead
+            check_added_file(state, file).ok();
+        }
+    }
+}
+
+fn add_file_content(state: &AppState, file_name: &Path, romfs_dir: &Path, names: &[PathBuf]) {
+    let mut file_content = vec![];
+    for name in names.iter() {
+        if let Ok(path_diff) = name.strip_prefix(romfs_dir) {
+            let name = Path::new("romfs")
+                .join(path_diff)
+                .to_string_lossy()
+                .replace('\\', "/");
+            file_content.push(name);
+        }
+    }
+    state
+        .file_content
+        .write()
+        .unwrap()
+        .insert(file_name.to_path_buf(), file_content);
+}
+
 #[tauri::command]
 fn assert_added_files(state: State<AppState>) -> Result<()> {
-    if *state.has_original_game_files.read().unwrap() {
+    assert_required_files(&state)
+}
+
+fn assert_required_files(state: &AppState) -> Result<()> {
+    let coverage = coverage(state)?;
+    if coverage.has_unlisted_inputs() || state.base_bundle.read().unwrap().is_some() {
         return Ok(());
     }
 
-    let mut err_res = vec![];
-    let state_files = state.required_files.read().unwrap();
-    let mut required_files = {
-        let mut map = HashMap::new();
-        for required_file in state_files.iter() {
-            map.insert(required_file, false);
-        }
-        map
-    };
-    let file_contents = state.file_content.read().unwrap();
-    for file_content in file_contents.values() {
-        for file in file_content.iter() {
-            if required_files.contains_key(file) {
-                required_files.insert(file, true);
-            }
-        }
-    }
-    required_files.into_iter().for_each(|(k, v)| {
-        if !v {
-            err_res.push(k.clone());
-        }
-    });
-    if err_res.is_empty() {
+    let missing_files = coverage.missing_files();
+    if missing_files.is_empty() {
         Ok(())
     } else {
-        Err(Error::RequiredFilesMissing(err_res))
+        Err(Error::RequiredFilesMissing(missing_files))
     }
 }
 
 #[tauri::command]
-fn remove_file(file_name: PathBuf, state: State<AppState>) -> Vec<PathBuf> {
-    let mut has_original_game_files = false;
-    let extension = file_name.extension().and_then(OsStr::to_str);
-    state.selected_files.write().unwrap().retain(|f| {
-        if extension == Some("xci") || extension == Some("nsp") {
-            has_original_game_files = true;
-        }
-        f != &file_name
-    });
-    *state.has_original_game_files.write().unwrap() = has_original_game_files;
+fn get_coverage(state: State<AppState>) -> Result<Coverage> {
+    coverage(&state)
+}
+
+fn coverage(state: &AppState) -> Result<Coverage> {
+    let files = state.selected_files.read().unwrap().clone();
+    let (inputs, _) = detect_inputs(
+        &files,
+        &state.file_content.read().unwrap(),
+        &state.required_files.read().unwrap(),
+    )?;
+    Ok(Coverage::new(
+        &inputs,
+        &state.file_content.read().unwrap(),
+        &state.required_files.read().unwrap(),
+    ))
+}
+
+#[tauri::command]
+fn remove_file(file_name: PathBuf, state: State<AppState>) -> Result<Vec<PathBuf>> {
+    state
+        .selected_files
+        .write()
+        .unwrap()
+        .retain(|f| f != &file_name);
     state.file_content.write().unwrap().remove(&file_name);
-    state.selected_files.read().unwrap().clone()
+    save_settings(&state)?;
+    Ok(state.selected_files.read().unwrap().clone())
 }
 
 #[tauri::command]
-async fn extract_assets(state: State<'_, AppState>, window: Window) -> Result<()> {
+async fn extract_assets(state: State<'_, AppState>, window: Window) -> Result<Vec<Conflict>> {
+    let log = RunLog::new(window.clone(), state.log.clone(), state.log_details.clone());
+    let result = extract(&state, &window, &log).await;
+    finish_log(log, result)
+}
+
+async fn extract(state: &AppState, window: &Window, log: &RunLog) -> Result<Vec<Conflict>> {
     let files = state.selected_files.read().unwrap().clone();
     let prod_key = state.prod_key.read().unwrap().clone();
-    let mut prod_key_required = false;
-    let progress = Arc::new(RwLock::new(0f64));
-    let max_progress = files.iter().fold(3u32, |acc, file| {
-        let file_name = file.to_string_lossy();
-        if file_name.ends_with(".xci") {
-            prod_key_required = true;
-            acc + 2
-        } else if file_name.ends_with(".nsp") {
-            prod_key_required = true;
-            acc + 1
-        } else {
-            acc
-        }
-    });
-    if prod_key_required && prod_key.is_none() {
-        return Err(Error::ProdKeyNotSet);
-    }
+    let (inputs, _) = detect_inputs(
+        &files,
+        &state.file_content.read().unwrap(),
+        &state.required_files.read().unwrap(),
+    )?;
+    let rules = AssetRules::new(
+        &state.asset_rules.read().unwrap(),
+        *state.optimize_png.read().unwrap(),
+    )?;
+    let progress = new_progress(window, &EXTRACT_PHASES, &inputs)?;
+    progress.add_total(Phase::Finish, 1);
+    let cache = Cache::open();
 
-    let cursor = io::Cursor::new(vec![]);
-    let builder = RwLock::new(tar::Builder::new(cursor));
+    // extracted layers must outlive bundling
+    let (_dirs, layers) = extract_layers(
+        window,
+        log,
+        &inputs,
+        prod_key.as_deref(),
+        cache.as_ref(),
+        &progress,
+    )
+    .await?;
 
-    for (index, file) in files.iter().enumerate() {
-        let dir = tempdir()?;
+    // game dumps may not have been listed when adding files, so required files are checked
+    // again, once their content is known
+    let game_dumps = inputs
+        .iter()
+        .zip(layers.iter())
+        .filter(|((_, kind), _)| kind.is_game_dump());
+    for ((file, _), layer) in game_dumps {
+        if let LayerSource::Dir(romfs_dir) = &layer.source {
+            add_file_content(state, file, romfs_dir, &list_dir(romfs_dir)?);
+        }
+    }
+    assert_required_files(state)?;
 
-        let file_name = file.to_string_lossy();
-        let file_message = format!(
-            "[{}/{}] Processing file {}",
-            index + 1,
-            files.len() + 1,
-            file_name
-        );
-        window.emit(
-            "extract_step",
-            format!("{}\nExtracting XCI...", file_message),
-        )?;
-        let window = window.clone();
-        let extension = Path::new(file_name.as_ref())
-            .extension()
-            .and_then(OsStr::to_str);
-        let romfs_dir = if extension == Some("xci") {
-            let romfs_dir = dir.path().join("romfs");
-            let exefs_dir = dir.path().join("exefs");
-            extract_xci(
-                window.clone(),
-                &dir,
-                &romfs_dir,
-                &exefs_dir,
-                file,
-                prod_key.as_ref().unwrap(),
-                progress.clone(),
-                max_progress,
-                &file_message,
-            )
-            .await?;
-            romfs_dir
-        } else if extension == Some("nsp") {
-            // TODO
-            todo!();
-        } else if extension == Some("zip") {
-            extract_zip(&dir, file);
-            find_romfs_dir(&dir)?
-        } else if extension == Some("7z") {
-            extract_7z(window.clone(), &dir, file).await?;
-            find_romfs_dir(&dir)?
-        } else {
-            return Err(error::Error::FileExtensionUnsupported);
-        };
-        bundle_assets(
-            window.clone(),
-            &builder,
-            &dir,
-            &romfs_dir,
-            progress.clone(),
-            max_progress,
-            &file_message,
+    let layered_fs = LayeredFs::new(layers)?;
+    for conflict in layered_fs.conflicts.iter() {
+        log.warn(
+            Some(Phase::Bundle),
+            Some(&conflict.by),
+            format!(
+                "{} of {} overridden",
+                conflict.asset.to_string_lossy(),
+                conflict.overridden.to_string_lossy()
+            ),
         )?;
     }
 
+    let file_message = format!("[{}/{}] Bundling assets", files.len() + 1, files.len() + 1);
+    let base_bundle = state
+        .base_bundle
+        .read()
+        .unwrap()
+        .as_ref()
+        .map(|(_, bundle_data)| bundle_data.clone());
+    let base_bundle = base_bundle.as_deref().map(BaseBundle::new).transpose()?;
+    let writer = TarWriter::spawn(BUNDLE_QUEUE_CAPACITY);
+    let manifest = bundle_layers(
+        window.clone(),
+        log,
+        writer.sender(),
+        &layered_fs,
+        cache.as_ref(),
+        base_bundle.as_ref(),
+        &rules,
+        &progress,
+        &file_message,
+    );
+    // an error of the writer is the cause of a failed bundling, so it is reported first
+    let builder = writer.finish()?;
+    let manifest = manifest?;
+
     let file_message = format!(
         "[{}/{}] All files extracted",
         files.len() + 1,
@@ -337,65 +461,351 @@ async fn extract_assets(state: State<'_, AppState>, window: Window) -> Result<()
     );
     *state.bundle_data.write().unwrap() = Some(finish_bundle_assets(
         window.clone(),
+        log,
         builder,
-        progress.clone(),
-        max_progress,
+        &manifest,
+        &progress,
         &file_message,
     )?);
+    if let Some(cache) = &cache {
+        cache.evict()?;
+    }
 
-    Ok(())
+    Ok(layered_fs.conflicts)
+}
+
+/// Compares the textures of all selected mods with the selected base game.
+#[tauri::command]
+async fn diff_mods(state: State<'_, AppState>, window: Window) -> Result<Vec<ModDiff>> {
+    let log = RunLog::new(window.clone(), state.log.clone(), state.log_details.clone());
+    let result = diff(&state, &window, &log).await;
+    finish_log(log, result)
+}
+
+async fn diff(state: &AppState, window: &Window, log: &RunLog) -> Result<Vec<ModDiff>> {
+    let files = state.selected_files.read().unwrap().clone();
+    let prod_key = state.prod_key.read().unwrap().clone();
+    let (inputs, base_count) = detect_inputs(
+        &files,
+        &state.file_content.read().unwrap(),
+        &state.required_files.read().unwrap(),
+    )?;
+    if base_count == 0 {
+        return Err(Error::BaseGameMissing);
+    }
+    let progress = new_progress(window, &DIFF_PHASES, &inputs)?;
+    progress.add_total(Phase::Compare, (inputs.len() - base_count) as u64);
+    let cache = Cache::open();
+
+    let (_dirs, layers) = extract_layers(
+        window,
+        log,
+        &inputs,
+        prod_key.as_deref(),
+        cache.as_ref(),
+        &progress,
+    )
+    .await?;
+
+    let (base, mods) = layers.split_at(base_count);
+    progress.start(Phase::Compare);
+    let mut diffs = vec![];
+    for layer in mods {
+        window.emit(
+            "extract_step",
+            format!("Comparing {}", layer.input.to_string_lossy()),
+        )?;
+        diffs.push(diff_mod(base, layer)?);
+        log.info(
+            Some(Phase::Compare),
+            Some(&layer.input),
+            "Compared with base game",
+        )?;
+        progress.advance(Phase::Compare, 1)?;
+    }
+    progress.complete(Phase::Compare)?;
+    if let Some(cache) = &cache {
+        cache.evict()?;
+    }
+    Ok(diffs)
+}
+
+/// Ends the log of a run with the error of a failed run and streams the remaining entries.
+fn finish_log<T>(log: RunLog, result: Result<T>) -> Result<T> {
+    if let Err(err) = &result {
+        log.error(None, err.path(), err.to_string()).ok();
+    }
+    log.flush().ok();
+    result
+}
+
+/// Creates the progress of a run, where decryption is measured by the bytes, which hactool
+/// decrypts while extracting all game dumps.
+///
+/// The decryption phase is left out, if there are no game dumps, so that it does not hold
+/// back the progress of the other phases.
+fn new_progress(
+    window: &Window,
+    phases: &[(Phase, f64)],
+    inputs: &[(PathBuf, InputKind)],
+) -> Result<Progress> {
+    let mut decrypt_bytes = 0;
+    for (file, kind) in inputs.iter().filter(|(_, kind)| kind.is_game_dump()) {
+        decrypt_bytes += fs::metadata(file).with_path(file)?.len() * kind.decrypt_passes();
+    }
+    let phases: Vec<_> = phases
+        .iter()
+        .copied()
+        .filter(|(phase, _)| *phase != Phase::Decrypt || decrypt_bytes > 0)
+        .collect();
+    let progress = Progress::new(window.clone(), &phases);
+    progress.add_total(Phase::Decrypt, decrypt_bytes);
+    Ok(progress)
+}
+
+/// Extracts the romfs of all inputs, which have to be read from disk.
+///
+/// Game dumps are looked up in the cache first and stored in it after their extraction.
+///
+/// The returned temporary directories contain the extracted layers and must be kept alive
+/// as long as the layers are used.
+async fn extract_layers(
+    window: &Window,
+    log: &RunLog,
+    inputs: &[(PathBuf, InputKind)],
+    prod_key: Option<&Path>,
+    cache: Option<&Cache>,
+    progress: &Progress,
+) -> Result<(Vec<TempDir>, Vec<Layer>)> {
+    let prod_key_required = inputs.iter().any(|(_, kind)| kind.is_game_dump());
+    let prod_key = match prod_key {
+        Some(prod_key) => prod_key,
+        None if prod_key_required => return Err(Error::ProdKeyNotSet),
+        None => Path::new(""),
+    };
+
+    let mut dirs = vec![];
+    let mut layers = vec![];
+    for (index, (file, kind)) in inputs.iter().enumerate() {
+        let dir = tempdir()?;
+
+        let file_name = file.to_string_lossy();
+        let file_message = format!(
+            "[{}/{}] Processing file {}",
+            index + 1,
+            inputs.len() + 1,
+            file_name
+        );
+        window.emit("extract_step", format!("{}\n{}", file_message, kind.step()))?;
+        log.info(None, Some(file), format!("Detected {}", kind.name()))?;
+        let window = window.clone();
+        let layer = match kind {
+            InputKind::Directory => Layer::dir(file, find_romfs_dir(file)?),
+            InputKind::Archive(ModArchive::Zip) => Layer::zip(file)?,
+            InputKind::Archive(archive) => Layer::dir(file, archive.extract(&dir, file)?),
+            kind @ (InputKind::Xci | InputKind::Nsp) => {
+                progress.start(Phase::Decrypt);
+                let key = match cache {
+                    Some(_) => {
+                        window.emit("extract_step", format!("{}\nHashing...", file_message))?;
+                        Some(file_key(file)?)
+                    }
+                    None => None,
+                };
+                let cache = cache.zip(key.as_deref());
+                match cache.and_then(|(cache, key)| cache.romfs(key)) {
+                    Some(romfs_dir) => {
+                        log.info(Some(Phase::Decrypt), Some(file), "Using cached romfs")?;
+                        let file_size = fs::metadata(file).with_path(file)?.len();
+                        progress.advance(Phase::Decrypt, file_size * kind.decrypt_passes())?;
+                        Layer::dir(file, romfs_dir)
+                    }
+                    None => {
+                        let romfs_dir = dir.path().join("romfs");
+                        let exefs_dir = dir.path().join("exefs");
+                        if *kind == InputKind::Xci {
+                            extract_xci(
+                                window.clone(),
+                                log,
+                                &dir,
+                                &romfs_dir,
+                                &exefs_dir,
+                                file,
+                                prod_key,
+                                progress,
+                                &file_message,
+                            )
+                            .await?;
+                        } else {
+                            extract_nsp(
+                                window.clone(),
+                                log,
+                                &dir,
+                                &romfs_dir,
+                                &exefs_dir,
+                                file,
+                                prod_key,
+                                progress,
+                                &file_message,
+                            )
+                            .await?;
+                        }
+                        match cache {
+                            Some((cache, key)) => {
+                                Layer::dir(file, cache.store_romfs(key, &romfs_dir)?)
+                            }
+                            None => Layer::dir(file, romfs_dir),
+                        }
+                    }
+                }
+            }
+        };
+        layers.push(layer);
+        dirs.push(dir);
+    }
+    Ok((dirs, layers))
+}
+
+/// Moves a selected file one position up in the load order, which lowers its precedence.
+#[tauri::command]
+fn move_file_up(file_name: PathBuf, state: State<AppState>) -> Result<Vec<PathBuf>> {
+    {
+        let mut files = state.selected_files.write().unwrap();
+        if let Some(index) = files.iter().position(|f| f == &file_name) {
+            if index > 0 {
+                files.swap(index - 1, index);
+            }
+        }
+    }
+    save_settings(&state)?;
+    Ok(state.selected_files.read().unwrap().clone())
+}
+
+#[tauri::command]
+fn set_output_format(output_format: OutputFormat, state: State<AppState>) -> Result<()> {
+    *state.output_format.write().unwrap() = output_format;
+    save_settings(&state)
+}
+
+/// Sets the rules, which select the encoding of extracted images.
+#[tauri::command]
+fn set_asset_rules(asset_rules: Vec<AssetRule>, state: State<AppState>) -> Result<()> {
+    AssetRules::new(&asset_rules, *state.optimize_png.read().unwrap())?;
+    *state.asset_rules.write().unwrap() = asset_rules;
+    save_settings(&state)
+}
+
+/// Enables an additional oxipng pass over all PNG images.
+#[tauri::command]
+fn set_optimize_png(optimize_png: bool, state: State<AppState>) -> Result<()> {
+    *state.optimize_png.write().unwrap() = optimize_png;
+    save_settings(&state)
 }
 
 #[tauri::command]
 fn save_bundle_data(state: State<AppState>) -> Result<()> {
     let home = env::var("HOME").ok().unwrap_or_default();
-    let default_path = if let "" = home.as_ref() {
+    let output_dir = state.output_dir.read().unwrap().clone();
+    let output_format = *state.output_format.read().unwrap();
+    let default_path = if let Some(output_dir) = &output_dir {
+        Some(output_dir.as_path())
+    } else if let "" = home.as_ref() {
         None
     } else {
         Some(Path::new(&home))
     };
-    let result = nfd2::open_save_dialog(None, default_path)?;
+    let result = match output_format {
+        OutputFormat::Directory | OutputFormat::Web => nfd2::open_pick_folder(default_path)?,
+        _ => nfd2::open_save_dialog(None, default_path)?,
+    };
 
     match result {
-        Response::Okay(mut file_path) => {
-            if file_path.file_name().is_none() {
-                file_path.set_file_name("shroom_kingdom_assets");
-            }
-            if file_path.extension().is_none() || file_path.extension().unwrap() != ".tar" {
-                file_path.set_extension("tar");
-            }
+        Response::Okay(file_path) => {
+            let file_path = output_format.output_path(file_path);
             if let Some(bundle_data) = &*state.bundle_data.read().unwrap() {
-                fs::write(file_path, bundle_data)?;
+                output_format.write(bundle_data, &file_path)?;
             }
-            Ok(())
+            *state.output_dir.write().unwrap() = match output_format {
+                OutputFormat::Directory | OutputFormat::Web => Some(file_path),
+                _ => file_path.parent().map(Path::to_path_buf),
+            };
+            save_settings(&state)
         }
         Response::OkayMultiple(_) | Response::Cancel => Err(error::Error::FileSelectCanceled),
     }
 }
 
-pub fn increase_progress(
-    window: Window,
-    progress: Arc<RwLock<f64>>,
-    max_progress: u32,
-) -> Result<()> {
-    let p = *progress.read().unwrap() + 1.;
-    *progress.write().unwrap() = p;
-    let extract_progress = (p as f64 / max_progress as f64) * 100.;
-    window.emit("extract_progress", extract_progress)?;
-    Ok(())
+/// Loads a bundle, which has been saved before, so that it can be updated incrementally or
+/// partial mods can be applied on top.
+#[tauri::command]
+fn load_base_bundle(state: State<AppState>) -> Result<PathBuf> {
+    let result = nfd2::dialog().filter("tar,gz,zst").open()?;
+
+    match result {
+        Response::Okay(file_path) => {
+            let bundle_data = match InputKind::detect(&file_path)? {
+                InputKind::Archive(ModArchive::Tar(compression)) => {
+                    read_tar(&file_path, compression)?
+                }
+                InputKind::Archive(ModArchive::Zip) | InputKind::Directory => {
+                    return Err(Error::BaseBundleFormatUnsupported)
+                }
+                _ => return Err(Error::FileExtensionUnsupported),
+            };
+            // the bundle is indexed once, so that a corrupt bundle is rejected right away
+            BaseBundle::new(&bundle_data).with_path(&file_path)?;
+            *state.base_bundle.write().unwrap() = Some((file_path.clone(), bundle_data));
+            Ok(file_path)
+        }
+        Response::OkayMultiple(_) => {
+            unreachable!();
+        }
+        Response::Cancel => Err(error::Error::FileSelectCanceled),
+    }
 }
 
-pub fn increase_progress_sync(
-    window: Arc<Mutex<Window>>,
-    progress: Arc<RwLock<f64>>,
-    max_progress: u32,
-) -> Result<()> {
-    let p = *progress.read().unwrap() + 1.;
-    *progress.write().unwrap() = p;
-    let extract_progress = (p as f64 / max_progress as f64) * 100.;
-    window
-        .lock()
-        .unwrap()
-        .emit("extract_progress", extract_progress)?;
-    Ok(())
+#[tauri::command]
+fn remove_base_bundle(state: State<AppState>) {
+    *state.base_bundle.write().unwrap() = None;
+}
+
+/// Removes all cached game dumps and assets, e.g. to free disk space.
+#[tauri::command]
+fn clear_cache() -> Result<()> {
+    match Cache::open() {
+        Some(cache) => cache.clear(),
+        None => Ok(()),
+    }
+}
+
+/// Sets whether debug entries are streamed to the log view and returns the entries of the
+/// current or last run, which the view shows then.
+#[tauri::command]
+fn set_log_details(state: State<AppState>, details: bool) -> Vec<LogEntry> {
+    // the log stays locked, so that no entry is streamed with the previous setting after the
+    // returned entries
+    let log = state.log.lock().unwrap();
+    state.log_details.store(details, Ordering::SeqCst);
+    log.iter()
+        .filter(|entry| details || entry.level != LogLevel::Debug)
+        .cloned()
+        .collect()
+}
+
+/// Saves the log of the last run as text, so that it can be attached to bug reports.
+#[tauri::command]
+fn export_log(state: State<AppState>) -> Result<PathBuf> {
+    let output_dir = state.output_dir.read().unwrap().clone();
+    match nfd2::open_save_dialog(Some("log"), output_dir.as_deref())? {
+        Response::Okay(file_path) => {
+            let file_path = if file_path.extension().is_none() {
+                file_path.with_extension("log")
+            } else {
+                file_path
+            };
+            fs::write(&file_path, format_log(&state.log.lock().unwrap())).with_path(&file_path)?;
+            Ok(file_path)
+        }
+        Response::OkayMultiple(_) | Response::Cancel => Err(error::Error::FileSelectCanceled),
+    }
 }
@@ -0,0 +1,101 @@
+use crate::{error::Error, tar_writer::TarEntry, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    io,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+/// Name of the manifest entry, which is embedded into every bundle.
+pub const MANIFEST_NAME: &str = "manifest.json";
+
+/// The bundle entries of a single asset file.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ManifestAsset {
+    /// Hash of the asset file and of everything affecting its processing.
+    pub hash: String,
+    pub entries: Vec<PathBuf>,
+}
+
+/// Describes from which asset files the entries of a bundle have been produced.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    pub assets: BTreeMap<PathBuf, ManifestAsset>,
+}
+
+impl Manifest {
+    pub fn to_json(&self) -> Result<Vec<u8>> {
+        serde_json::to_vec_pretty(self)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err).into())
+    }
+}
+
+/// A bundle, which has been saved before and is updated incrementally.
+pub struct BaseBundle<'a> {
+    data: &'a [u8],
+    pub manifest: Option<Manifest>,
+    entries: HashMap<PathBuf, (tar::Header, Range<usize>)>,
+}
+
+impl<'a> BaseBundle<'a> {
+    /// Indexes all entries of a bundle.
+    ///
+    /// Bundles of older versions do not contain a manifest, so none of their entries can be
+    /// reused, but they can still be overridden.
+    pub fn new(data: &'a [u8]) -> Result<Self> {
+        let mut manifest = None;
+        let mut entries = HashMap::new();
+        for entry in tar::Archive::new(data).entries()? {
+            let entry = entry?;
+            let path = entry.path()?.into_owned();
+            let start = entry.raw_file_position() as usize;
+            let range = start..start + entry.size() as usize;
+            if path.as_path() == Path::new(MANIFEST_NAME) {
+                manifest = Some(
+                    serde_json::from_slice(&data[range])
+                        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?,
+                );
+            } else {
+                entries.insert(path, (entry.header().clone(), range));
+            }
+        }
+        Ok(Self {
+            data,
+            manifest,
+            entries,
+        })
+    }
+
+    /// Returns the bundle entries of an asset, if it has been bundled with the same hash.
+    pub fn reusable_entries(&self, asset: &Path, hash: &str) -> Option<&ManifestAsset> {
+        self.manifest
+            .as_ref()?
+            .assets
+            .get(asset)
+            .filter(|manifest_asset| manifest_asset.hash == hash)
+            .filter(|manifest_asset| {
+                manifest_asset
+                    .entries
+                    .iter()
+                    .all(|entry| self.entries.contains_key(entry))
+            })
+    }
+
+    pub fn paths(&self) -> impl Iterator<Item = &PathBuf> {
+        self.entries.keys()
+    }
+
+    /// Copies an entry of this bundle, so that it can be written to a new bundle.
+    pub fn entry(&self, path: &Path) -> Result<TarEntry> {
+        let (header, range) = self
+            .entries
+            .get(path)
+            .ok_or_else(|| Error::from(io::Error::from(io::ErrorKind::NotFound)))?;
+        Ok(TarEntry {
+            path: path.to_path_buf(),
+            header: header.clone(),
+            data: self.data[range.clone()].to_vec(),
+        })
+    }
+}
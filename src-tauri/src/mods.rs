@@ -1,92 +1,438 @@
-use crate::Result;
+use crate::{bundle::find_romfs_dir_in_names, error::ResultExt, Result};
+use flate2::read::GzDecoder;
+use sevenz_rust::{Password, SevenZArchiveEntry, SevenZReader};
 use std::{
     fs::{create_dir_all, File},
-    io,
-    path::{Path, PathBuf},
-};
-use tauri::{
-    api::process::{Command, CommandEvent, TerminatedPayload},
-    async_runtime, Window,
+    io::{self, BufReader, Read},
+    path::{Component, Path, PathBuf},
 };
 use tempfile::TempDir;
 
-pub fn extract_zip(dir: &TempDir, file: &PathBuf) {
-    let file = File::open(file).unwrap();
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TarCompression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+/// Archive formats in which mods are distributed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ModArchive {
+    Zip,
+    SevenZ,
+    Tar(TarCompression),
+    Rar,
+}
+
+impl ModArchive {
+    /// Lists all files of the archive.
+    pub fn list(&self, path: &Path) -> Result<Vec<PathBuf>> {
+        match self {
+            ModArchive::Zip => list_zip(path),
+            ModArchive::SevenZ => list_7z(path),
+            ModArchive::Tar(compression) => list_tar(path, *compression),
+            ModArchive::Rar => list_rar(path),
+        }
+    }
+
+    /// Extracts the `Model` and `Pack` directories of the romfs contained in the archive
+    /// and returns the extracted romfs directory.
+    pub fn extract(&self, dir: &TempDir, path: &Path) -> Result<PathBuf> {
+        let names = self.list(path)?;
+        let romfs_dir = find_romfs_dir_in_names(names.iter().map(PathBuf::as_path))?;
+        let model_dir = romfs_dir.join("Model");
+        let pack_dir = romfs_dir.join("Pack");
+        let is_asset = |name: &Path| name.starts_with(&model_dir) || name.starts_with(&pack_dir);
+
+        match self {
+            ModArchive::Zip => extract_zip(dir, path, is_asset)?,
+            ModArchive::SevenZ => extract_7z(dir, path, is_asset)?,
+            ModArchive::Tar(compression) => extract_tar(dir, path, *compression, is_asset)?,
+            ModArchive::Rar => extract_rar(dir, path, is_asset)?,
+        }
 
-    let mut archive = zip::ZipArchive::new(file).unwrap();
-    let temp_dir = dir.path().to_path_buf();
+        Ok(dir.path().join(romfs_dir))
+    }
+}
 
+/// Normalizes a path of an archive entry and rejects paths escaping the archive.
+fn entry_path(name: &str) -> Option<PathBuf> {
+    let name = name.replace('\\', "/");
+    let mut path = PathBuf::new();
+    for component in Path::new(&name).components() {
+        match component {
+            Component::Normal(component) => path.push(component),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    Some(path)
+}
+
+fn create_file(dir: &TempDir, name: &Path) -> Result<File> {
+    let outpath = dir.path().join(name);
+    if let Some(p) = outpath.parent() {
+        create_dir_all(p).with_path(p)?;
+    }
+    File::create(&outpath).with_path(&outpath)
+}
+
+fn list_zip(path: &Path) -> Result<Vec<PathBuf>> {
+    let archive = zip::ZipArchive::new(File::open(path).with_path(path)?).with_path(path)?;
+    Ok(archive
+        .file_names()
+        .filter(|name| !name.ends_with('/'))
+        .filter_map(entry_path)
+        .collect())
+}
+
+fn extract_zip(dir: &TempDir, path: &Path, is_asset: impl Fn(&Path) -> bool) -> Result<()> {
+    let mut archive = zip::ZipArchive::new(File::open(path).with_path(path)?).with_path(path)?;
     for i in 0..archive.len() {
-        let mut file = archive.by_index(i).unwrap();
-        let mut outpath = temp_dir.clone();
-        match file.enclosed_name() {
-            Some(path) => outpath.push(path),
-            None => continue,
-        };
+        let mut file = archive.by_index(i).with_path(path)?;
+        match file.enclosed_name().map(Path::to_path_buf) {
+            Some(name) if file.is_file() && is_asset(&name) => {
+                io::copy(&mut file, &mut create_file(dir, &name)?).with_path(path)?;
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn list_7z(path: &Path) -> Result<Vec<PathBuf>> {
+    let reader = SevenZReader::open(path, Password::empty()).with_path(path)?;
+    Ok(reader
+        .archive()
+        .files
+        .iter()
+        .filter(|entry| !entry.is_directory())
+        .filter_map(|entry| entry_path(entry.name()))
+        .collect())
+}
 
-        if (*file.name()).ends_with('/') {
-            println!("File {} extracted to \"{}\"", i, outpath.display());
-            create_dir_all(&outpath).unwrap();
-        } else {
-            println!(
-                "File {} extracted to \"{}\" ({} bytes)",
-                i,
-                outpath.display(),
-                file.size()
-            );
-            if let Some(p) = outpath.parent() {
-                if !p.exists() {
-                    create_dir_all(&p).unwrap();
+fn extract_7z(dir: &TempDir, path: &Path, is_asset: impl Fn(&Path) -> bool) -> Result<()> {
+    let mut reader = SevenZReader::open(path, Password::empty()).with_path(path)?;
+    reader
+        .for_each_entries(|entry: &SevenZArchiveEntry, data| {
+            match entry_path(entry.name()) {
+                Some(name) if !entry.is_directory() && is_asset(&name) => {
+                    let outpath = dir.path().join(name);
+                    if let Some(p) = outpath.parent() {
+                        create_dir_all(p)?;
+                    }
+                    io::copy(data, &mut File::create(&outpath)?)?;
                 }
+                _ => {
+                    // entries of solid archives depend on the previous ones, so we can't skip them
+                    io::copy(data, &mut io::sink())?;
+                }
+            }
+            Ok(true)
+        })
+        .with_path(path)?;
+    Ok(())
+}
+
+fn open_tar(path: &Path, compression: TarCompression) -> Result<tar::Archive<Box<dyn Read>>> {
+    Ok(tar::Archive::new(decode_tar(path, compression)?))
+}
+
+fn decode_tar(path: &Path, compression: TarCompression) -> Result<Box<dyn Read>> {
+    let file = BufReader::new(File::open(path).with_path(path)?);
+    Ok(match compression {
+        TarCompression::None => Box::new(file),
+        TarCompression::Gzip => Box::new(GzDecoder::new(file)),
+        TarCompression::Zstd => Box::new(zstd::stream::read::Decoder::new(file).with_path(path)?),
+    })
+}
+
+/// Reads a possibly compressed tar as an uncompressed tar.
+pub fn read_tar(path: &Path, compression: TarCompression) -> Result<Vec<u8>> {
+    let mut data = vec![];
+    decode_tar(path, compression)?
+        .read_to_end(&mut data)
+        .with_path(path)?;
+    Ok(data)
+}
+
+fn list_tar(path: &Path, compression: TarCompression) -> Result<Vec<PathBuf>> {
+    let mut archive = open_tar(path, compression)?;
+    let mut names = vec![];
+    for entry in archive.entries().with_path(path)? {
+        let entry = entry.with_path(path)?;
+        if entry.header().entry_type().is_file() {
+            if let Some(name) = entry_path(&entry.path().with_path(path)?.to_string_lossy()) {
+                names.push(name);
             }
-            let mut outfile = File::create(&outpath).unwrap();
-            io::copy(&mut file, &mut outfile).unwrap();
         }
+    }
+    Ok(names)
+}
 
-        #[cfg(unix)]
-        {
-            use std::{fs, os::unix::fs::PermissionsExt};
+fn extract_tar(
+    dir: &TempDir,
+    path: &Path,
+    compression: TarCompression,
+    is_asset: impl Fn(&Path) -> bool,
+) -> Result<()> {
+    let mut archive = open_tar(path, compression)?;
+    for entry in archive.entries().with_path(path)? {
+        let mut entry = entry.with_path(path)?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        match entry_path(&entry.path().with_path(path)?.to_string_lossy()) {
+            Some(name) if is_asset(&name) => {
+                io::copy(&mut entry, &mut create_file(dir, &name)?).with_path(path)?;
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
 
-            if let Some(mode) = file.unix_mode() {
-                fs::set_permissions(&outpath, fs::Permissions::from_mode(mode)).unwrap();
+fn list_rar(path: &Path) -> Result<Vec<PathBuf>> {
+    let archive = unrar::Archive::new(path)
+        .open_for_listing()
+        .with_path(path)?;
+    let mut names = vec![];
+    for entry in archive {
+        let entry = entry.with_path(path)?;
+        if entry.is_file() {
+            if let Some(name) = entry_path(&entry.filename.to_string_lossy()) {
+                names.push(name);
             }
         }
     }
+    Ok(names)
 }
 
-pub async fn extract_7z(window: Window, dir: &TempDir, file: &Path) -> Result<()> {
-    let (mut rx_sidecar, _) = Command::new_sidecar("7z-sk")
-        .expect("failed to create `7z` binary command")
-        .args(vec![
-            "x",
-            "-y",
-            "-bd",
-            &format!("-o{}", dir.path().to_string_lossy()),
-            &file.to_string_lossy(),
-        ])
-        .spawn()
-        .expect("Failed to spawn 7zip");
-
-    let (tx, mut rx) = async_runtime::channel(1);
-    {
-        let window = window.clone();
-        async_runtime::spawn(async move {
-            while let Some(event) = rx_sidecar.recv().await {
-                if let CommandEvent::Stdout(line) = event {
-                    window
-                        .emit("extract_message", line)
-                        .expect("failed to emit event");
+fn extract_rar(dir: &TempDir, path: &Path, is_asset: impl Fn(&Path) -> bool) -> Result<()> {
+    let mut archive = unrar::Archive::new(path)
+        .open_for_processing()
+        .with_path(path)?;
+    while let Some(header) = archive.read_header().with_path(path)? {
+        let entry = header.entry();
+        archive = match entry_path(&entry.filename.to_string_lossy()) {
+            Some(name) if entry.is_file() && is_asset(&name) => {
+                let outpath = dir.path().join(name);
+                if let Some(p) = outpath.parent() {
+                    create_dir_all(p).with_path(p)?;
                 }
+                header.extract_to(outpath).with_path(path)?
             }
-            tx.send(CommandEvent::Terminated(TerminatedPayload {
-                code: Some(0),
-                signal: None,
-            }))
-            .await
-            .unwrap();
-        });
+            _ => header.skip().with_path(path)?,
+        };
     }
-    rx.recv().await;
-
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{write::GzEncoder, Compression};
+    use std::{
+        fs,
+        io::{Cursor, Write},
+    };
+    use tempfile::tempdir;
+    use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+    const FILES: [&str; 2] = ["romfs/Model/MW_Field_plain.zs", "romfs/Pack/MW_Model.pack"];
+    const NOISE: &[u8] = include_bytes!("../tests/fixtures/noise.bin");
+
+    /// Incompressible file content, so that truncated archives end within the data.
+    fn file_data() -> Vec<u8> {
+        NOISE[..4096].to_vec()
+    }
+
+    fn zip_archive(method: CompressionMethod) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(vec![]));
+        for name in FILES {
+            writer
+                .start_file(name, FileOptions::default().compression_method(method))
+                .unwrap();
+            writer.write_all(&file_data()).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn tar_archive() -> Vec<u8> {
+        let mut builder = tar::Builder::new(vec![]);
+        for name in FILES {
+            let data = file_data();
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, name, &data[..]).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    /// Asserts that listing and extracting the given archive data fails without panicking.
+    fn assert_fails(archive: ModArchive, file_name: &str, data: &[u8]) {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join(file_name);
+        fs::write(&path, data).unwrap();
+        assert!(
+            archive.list(&path).is_err(),
+            "listing {} succeeded",
+            file_name
+        );
+        let out_dir = tempdir().unwrap();
+        assert!(
+            archive.extract(&out_dir, &path).is_err(),
+            "extracting {} succeeded",
+            file_name
+        );
+    }
+
+    #[test]
+    fn fails_on_empty_archives() {
+        assert_fails(ModArchive::Zip, "mod.zip", &[]);
+        assert_fails(ModArchive::SevenZ, "mod.7z", &[]);
+        assert_fails(ModArchive::Rar, "mod.rar", &[]);
+    }
+
+    #[test]
+    fn fails_on_truncated_zip() {
+        let data = zip_archive(CompressionMethod::Deflated);
+        assert_fails(ModArchive::Zip, "mod.zip", &data[..data.len() / 2]);
+        // the end of central directory record is cut off
+        assert_fails(ModArchive::Zip, "mod.zip", &data[..data.len() - 10]);
+    }
+
+    #[test]
+    fn fails_on_malformed_zip() {
+        let mut data = b"PK\x03\x04".to_vec();
+        data.extend(file_data());
+        assert_fails(ModArchive::Zip, "mod.zip", &data);
+    }
+
+    #[test]
+    fn fails_on_corrupt_zip_entry() {
+        let mut data = zip_archive(CompressionMethod::Stored);
+        // flip a byte within the data of the first entry, which is caught by its checksum
+        data[100] ^= 0xff;
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("mod.zip");
+        fs::write(&path, &data).unwrap();
+        assert!(ModArchive::Zip.list(&path).is_ok());
+        let out_dir = tempdir().unwrap();
+        assert!(ModArchive::Zip.extract(&out_dir, &path).is_err());
+    }
+
+    #[test]
+    fn fails_on_truncated_and_malformed_7z() {
+        let signature = b"7z\xbc\xaf\x27\x1c\x00\x04";
+        assert_fails(ModArchive::SevenZ, "mod.7z", &signature[..6]);
+        let mut data = signature.to_vec();
+        data.extend(file_data());
+        assert_fails(ModArchive::SevenZ, "mod.7z", &data);
+    }
+
+    #[test]
+    fn fails_on_truncated_tar() {
+        let data = tar_archive();
+        // the second header is cut in half
+        let second_header = 512 + file_data().len();
+        assert_fails(
+            ModArchive::Tar(TarCompression::None),
+            "mod.tar",
+            &data[..second_header + 256],
+        );
+        // the data of the first entry is cut off
+        assert_fails(
+            ModArchive::Tar(TarCompression::None),
+            "mod.tar",
+            &data[..1024],
+        );
+    }
+
+    #[test]
+    fn fails_on_malformed_tar() {
+        let mut data = tar_archive();
+        // the header checksum does not match anymore
+        data[0] ^= 0xff;
+        assert_fails(ModArchive::Tar(TarCompression::None), "mod.tar", &data);
+    }
+
+    #[test]
+    fn fails_on_truncated_compressed_tar() {
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(&tar_archive()).unwrap();
+        let data = encoder.finish().unwrap();
+        assert_fails(
+            ModArchive::Tar(TarCompression::Gzip),
+            "mod.tar.gz",
+            &data[..data.len() / 2],
+        );
+
+        let data = zstd::encode_all(&tar_archive()[..], 0).unwrap();
+        assert_fails(
+            ModArchive::Tar(TarCompression::Zstd),
+            "mod.tar.zst",
+            &data[..data.len() / 2],
+        );
+    }
+
+    #[test]
+    fn reads_compressed_tar_as_uncompressed_tar() {
+        let dir = tempdir().unwrap();
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(&tar_archive()).unwrap();
+        let compressed = [
+            (TarCompression::None, "bundle.tar", tar_archive()),
+            (
+                TarCompression::Gzip,
+                "bundle.tar.gz",
+                encoder.finish().unwrap(),
+            ),
+            (
+                TarCompression::Zstd,
+                "bundle.tar.zst",
+                zstd::encode_all(&tar_archive()[..], 0).unwrap(),
+            ),
+        ];
+        for (compression, file_name, data) in compressed {
+            let path = dir.path().join(file_name);
+            fs::write(&path, data).unwrap();
+            assert!(read_tar(&path, compression).unwrap() == tar_archive());
+        }
+    }
+
+    #[test]
+    fn fails_on_malformed_compressed_tar() {
+        assert_fails(
+            ModArchive::Tar(TarCompression::Gzip),
+            "mod.tar.gz",
+            &file_data(),
+        );
+        assert_fails(
+            ModArchive::Tar(TarCompression::Zstd),
+            "mod.tar.zst",
+            &file_data(),
+        );
+    }
+
+    #[test]
+    fn fails_on_truncated_and_malformed_rar() {
+        let signature = b"Rar!\x1a\x07\x01\x00";
+        assert_fails(ModArchive::Rar, "mod.rar", &signature[..]);
+        let mut data = signature.to_vec();
+        data.extend(file_data());
+        assert_fails(ModArchive::Rar, "mod.rar", &data);
+    }
+
+    #[test]
+    fn rejects_entries_escaping_the_archive() {
+        assert_eq!(
+            entry_path("romfs\\Model\\MW_Model.pack"),
+            Some(PathBuf::from("romfs/Model/MW_Model.pack"))
+        );
+        assert_eq!(entry_path("romfs/Model/../../x"), None);
+        assert_eq!(entry_path("/romfs/Model/x"), None);
+    }
+}
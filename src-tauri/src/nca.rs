@@ -0,0 +1,370 @@
+use crate::{
+    error::{Error, ResultExt},
+    keys::Keys,
+    Result,
+};
+use aes::{
+    cipher::{generic_array::GenericArray, BlockDecrypt, BlockEncrypt, KeyInit},
+    Aes128,
+};
+use std::{collections::HashMap, convert::TryInto, fs::File, io::Read, path::Path};
+
+const SECTOR_SIZE: usize = 0x200;
+const HEADER_SIZE: usize = 2 * SECTOR_SIZE;
+/// Size of the header together with the headers of all four sections.
+const FULL_HEADER_SIZE: usize = HEADER_SIZE + 4 * FS_HEADER_SIZE;
+const MAGIC_OFFSET: usize = 0x200;
+const CONTENT_TYPE_OFFSET: usize = 0x205;
+const KEY_GENERATION_OLD_OFFSET: usize = 0x206;
+const KEY_AREA_KEY_INDEX_OFFSET: usize = 0x207;
+const KEY_GENERATION_OFFSET: usize = 0x220;
+const RIGHTS_ID_OFFSET: usize = 0x230;
+const SECTION_TABLE_OFFSET: usize = 0x240;
+const KEY_AREA_OFFSET: usize = 0x300;
+/// Index of the AES-128-CTR key within the key area.
+const CTR_KEY_INDEX: usize = 2;
+const MEDIA_SIZE: u64 = 0x200;
+
+const FS_HEADER_SIZE: usize = 0x200;
+const FS_TYPE_OFFSET: usize = 0x2;
+const HASH_TYPE_OFFSET: usize = 0x3;
+const ENCRYPTION_TYPE_OFFSET: usize = 0x4;
+/// Offset and size of the last IVFC level, which contains the romfs.
+const ROMFS_LEVEL_OFFSET: usize = 0x90;
+const SECTION_COUNTER_OFFSET: usize = 0x140;
+
+const CONTENT_TYPE_PROGRAM: u8 = 0;
+const FS_TYPE_ROMFS: u8 = 0;
+const HASH_TYPE_IVFC: u8 = 3;
+const ENCRYPTION_TYPE_NONE: u8 = 1;
+const ENCRYPTION_TYPE_CTR: u8 = 3;
+
+/// The decrypted header of an NCA including its section headers.
+pub struct NcaHeader(Vec<u8>);
+
+/// The romfs section of an NCA.
+pub struct RomfsSection {
+    /// Offset of the romfs within the NCA.
+    pub offset: u64,
+    pub size: u64,
+    /// The initial AES-128-CTR counter, if the section is encrypted.
+    pub counter: Option<[u8; 16]>,
+}
+
+impl NcaHeader {
+    /// Reads the header of an NCA, which starts at the current position of the reader.
+    pub fn read(reader: &mut impl Read, nca: &Path, keys: &Keys) -> Result<Self> {
+        let mut header = vec![0; FULL_HEADER_SIZE];
+        reader.read_exact(&mut header).with_path(nca)?;
+        decrypt_header(&mut header, &keys.get("header_key")?)?;
+        if &header[MAGIC_OFFSET..MAGIC_OFFSET + 3] != b"NCA" {
+            return Err(Error::NcaHeaderInvalid(nca.to_path_buf()));
+        }
+        Ok(Self(header))
+    }
+
+    /// Returns the romfs of a program, but not of updates, which only contain patches.
+    pub fn program_romfs(&self) -> Option<RomfsSection> {
+        if self.0[CONTENT_TYPE_OFFSET] != CONTENT_TYPE_PROGRAM {
+            return None;
+        }
+        (0..4).find_map(|index| {
+            let fs_header = &self.0[HEADER_SIZE + index * FS_HEADER_SIZE..][..FS_HEADER_SIZE];
+            let counter = match fs_header[ENCRYPTION_TYPE_OFFSET] {
+                ENCRYPTION_TYPE_NONE => None,
+                ENCRYPTION_TYPE_CTR => {
+                    // the upper half of the counter is stored in reverse byte order
+                    let mut counter = [0; 16];
+                    counter[..8].copy_from_slice(
+                        &fs_header[SECTION_COUNTER_OFFSET..SECTION_COUNTER_OFFSET + 8],
+                    );
+                    counter[..8].reverse();
+                    Some(counter)
+                }
+                _ => return None,
+            };
+            if fs_header[FS_TYPE_OFFSET] != FS_TYPE_ROMFS
+                || fs_header[HASH_TYPE_OFFSET] != HASH_TYPE_IVFC
+            {
+                return None;
+            }
+            let entry = &self.0[SECTION_TABLE_OFFSET + index * 0x10..];
+            let start = u32::from_le_bytes(entry[..4].try_into().unwrap()) as u64 * MEDIA_SIZE;
+            let level = &fs_header[ROMFS_LEVEL_OFFSET..];
+            let offset = u64::from_le_bytes(level[..8].try_into().unwrap());
+            Some(RomfsSection {
+                offset: start.checked_add(offset)?,
+                size: u64::from_le_bytes(level[8..16].try_into().unwrap()),
+                counter,
+            })
+        })
+    }
+
+    /// Decrypts the key of encrypted sections, which is either the title key of the given
+    /// rights ID or stored in the key area.
+    pub fn content_key(
+        &self,
+        keys: &Keys,
+        title_keys: &HashMap<[u8; 16], [u8; 16]>,
+    ) -> Result<[u8; 16]> {
+        let master_key_revision = master_key_revision(&self.0);
+        match rights_id(&self.0) {
+            Some(rights_id) => {
+                let title_key = title_keys
+                    .get(&rights_id)
+                    .ok_or_else(|| Error::TitleKeyMissing(hex::encode(rights_id)))?;
+                let name = format!("titlekek_{:02x}", master_key_revision);
+                decrypt_key(title_key, keys, &name)
+            }
+            None => {
+                let key_area_key = match self.0[KEY_AREA_KEY_INDEX_OFFSET] {
+                    0 => "application",
+                    1 => "ocean",
+                    _ => "system",
+                };
+                let name = format!("key_area_key_{}_{:02x}", key_area_key, master_key_revision);
+                let offset = KEY_AREA_OFFSET + CTR_KEY_INDEX * 16;
+                decrypt_key(&self.0[offset..offset + 16], keys, &name)
+            }
+        }
+    }
+}
+
+/// Reads the key generation of an NCA and makes sure, that the matching master key is
+/// available in the given keys.
+///
+/// hactool does not fail, if a master key is missing, but silently produces no output,
+/// so we have to check this ourselves before extracting anything. The same applies to the
+/// title key encryption key of NCAs with a rights ID.
+pub fn assert_key_generation(nca: &Path, keys: &Keys) -> Result<()> {
+    let header = read_header(nca, &keys.get("header_key")?)?;
+    let master_key_revision = master_key_revision(&header);
+    keys.get(&format!("master_key_{:02x}", master_key_revision))?;
+    if rights_id(&header).is_some() {
+        keys.get(&format!("titlekek_{:02x}", master_key_revision))?;
+    }
+    Ok(())
+}
+
+/// Reads the rights ID of an NCA, if its content is encrypted with a title key.
+pub fn read_rights_id(nca: &Path, keys: &Keys) -> Result<Option<[u8; 16]>> {
+    let header = read_header(nca, &keys.get("header_key")?)?;
+    Ok(rights_id(&header))
+}
+
+fn read_master_key_revision(nca: &Path, header_key: &[u8]) -> Result<u8> {
+    Ok(master_key_revision(&read_header(nca, header_key)?))
+}
+
+fn read_header(nca: &Path, header_key: &[u8]) -> Result<[u8; HEADER_SIZE]> {
+    let mut header = [0u8; HEADER_SIZE];
+    File::open(nca)
+        .and_then(|mut file| file.read_exact(&mut header))
+        .with_path(nca)?;
+    decrypt_header(&mut header, header_key)?;
+
+    if &header[MAGIC_OFFSET..MAGIC_OFFSET + 3] != b"NCA" {
+        return Err(Error::NcaHeaderInvalid(nca.to_path_buf()));
+    }
+    Ok(header)
+}
+
+fn master_key_revision(header: &[u8]) -> u8 {
+    let key_generation = header[KEY_GENERATION_OLD_OFFSET].max(header[KEY_GENERATION_OFFSET]);
+    key_generation.saturating_sub(1)
+}
+
+/// The rights ID of a decrypted header, unless it is zero, since no title key is needed then.
+fn rights_id(header: &[u8]) -> Option<[u8; 16]> {
+    let rights_id: [u8; 16] = header[RIGHTS_ID_OFFSET..RIGHTS_ID_OFFSET + 16]
+        .try_into()
+        .unwrap();
+    Some(rights_id).filter(|rights_id| rights_id.iter().any(|b| *b != 0))
+}
+
+/// Decrypts a key with the key of the given name using AES-128-ECB.
+fn decrypt_key(data: &[u8], keys: &Keys, name: &str) -> Result<[u8; 16]> {
+    let key = keys.get(name)?;
+    if key.len() != 16 {
+        return Err(Error::KeyInvalid(name.to_string()));
+    }
+    let mut block = GenericArray::clone_from_slice(data);
+    Aes128::new(GenericArray::from_slice(&key)).decrypt_block(&mut block);
+    Ok(block.into())
+}
+
+/// Decrypts the header in place with AES-128-XTS, using Nintendo's big endian sector tweak.
+fn decrypt_header(header: &mut [u8], header_key: &[u8]) -> Result<()> {
+    if header_key.len() != 32 {
+        return Err(Error::KeyInvalid("header_key".to_string()));
+    }
+    let data_cipher = Aes128::new(GenericArray::from_slice(&header_key[..16]));
+    let tweak_cipher = Aes128::new(GenericArray::from_slice(&header_key[16..]));
+
+    for (sector_index, sector) in header.chunks_mut(SECTOR_SIZE).enumerate() {
+        let mut tweak = GenericArray::from((sector_index as u128).to_be_bytes());
+        tweak_cipher.encrypt_block(&mut tweak);
+        let mut tweak = u128::from_le_bytes(tweak.into());
+
+        for block in sector.chunks_mut(16) {
+            let tweak_bytes = tweak.to_le_bytes();
+            let mut buf = GenericArray::clone_from_slice(block);
+            buf.iter_mut()
+                .zip(tweak_bytes.iter())
+                .for_each(|(b, t)| *b ^= t);
+            data_cipher.decrypt_block(&mut buf);
+            block
+                .iter_mut()
+                .zip(buf.iter().zip(tweak_bytes.iter()))
+                .for_each(|(b, (d, t))| *b = d ^ t);
+
+            let carry = tweak >> 127;
+            tweak <<= 1;
+            if carry == 1 {
+                tweak ^= 0x87;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    /// Header encrypted with an independent AES-128-XTS implementation, whose tweak is the
+    /// big endian sector index.
+    const ENCRYPTED_HEADER: &[u8] = include_bytes!("../tests/fixtures/nca_header.bin");
+
+    fn header_key() -> Vec<u8> {
+        (0..32).collect()
+    }
+
+    fn plain_header() -> Vec<u8> {
+        let mut header = vec![0; HEADER_SIZE];
+        for (i, b) in header[..SECTOR_SIZE].iter_mut().enumerate() {
+            *b = (i * 7 % 256) as u8;
+        }
+        header[MAGIC_OFFSET..MAGIC_OFFSET + 4].copy_from_slice(b"NCA3");
+        header[KEY_GENERATION_OLD_OFFSET] = 2;
+        header[KEY_GENERATION_OFFSET] = 5;
+        header
+    }
+
+    /// Encrypts a header with AES-128-XTS, so that NCAs can be built for tests.
+    pub fn encrypt_header(header: &mut [u8], header_key: &[u8]) {
+        let data_cipher = Aes128::new(GenericArray::from_slice(&header_key[..16]));
+        let tweak_cipher = Aes128::new(GenericArray::from_slice(&header_key[16..]));
+        for (sector_index, sector) in header.chunks_mut(SECTOR_SIZE).enumerate() {
+            let mut tweak = GenericArray::from((sector_index as u128).to_be_bytes());
+            tweak_cipher.encrypt_block(&mut tweak);
+            let mut tweak = u128::from_le_bytes(tweak.into());
+            for block in sector.chunks_mut(16) {
+                let tweak_bytes = tweak.to_le_bytes();
+                let mut buf = GenericArray::clone_from_slice(block);
+                buf.iter_mut()
+                    .zip(tweak_bytes.iter())
+                    .for_each(|(b, t)| *b ^= t);
+                data_cipher.encrypt_block(&mut buf);
+                block
+                    .iter_mut()
+                    .zip(buf.iter().zip(tweak_bytes.iter()))
+                    .for_each(|(b, (d, t))| *b = d ^ t);
+                tweak = (tweak << 1) ^ if tweak >> 127 == 1 { 0x87 } else { 0 };
+            }
+        }
+    }
+
+    pub fn read_keys(dir: &Path, keys: &[(&str, &[u8])]) -> Keys {
+        let prod_key = dir.join("prod.keys");
+        let content: String = keys
+            .iter()
+            .map(|(name, key)| format!("{} = {}\n", name, hex::encode(key)))
+            .collect::<Vec<_>>()
+            .concat();
+        fs::write(&prod_key, content).unwrap();
+        Keys::read(&prod_key).unwrap()
+    }
+
+    #[test]
+    fn decrypts_header_with_big_endian_sector_tweak() {
+        let mut header = ENCRYPTED_HEADER.to_vec();
+        decrypt_header(&mut header, &header_key()).unwrap();
+        assert_eq!(header, plain_header());
+    }
+
+    #[test]
+    fn encrypts_header_like_fixture() {
+        let mut header = plain_header();
+        encrypt_header(&mut header, &header_key());
+        assert_eq!(header, ENCRYPTED_HEADER);
+    }
+
+    #[test]
+    fn rejects_header_key_of_wrong_length() {
+        let mut header = ENCRYPTED_HEADER.to_vec();
+        assert!(matches!(
+            decrypt_header(&mut header, &header_key()[..16]),
+            Err(Error::KeyInvalid(_))
+        ));
+    }
+
+    #[test]
+    fn derives_master_key_revision_from_newer_key_generation() {
+        let dir = tempdir().unwrap();
+        let nca = dir.path().join("program.nca");
+        fs::write(&nca, ENCRYPTED_HEADER).unwrap();
+        assert_eq!(read_master_key_revision(&nca, &header_key()).unwrap(), 4);
+    }
+
+    #[test]
+    fn requires_master_key_of_key_generation() {
+        let dir = tempdir().unwrap();
+        let nca = dir.path().join("program.nca");
+        fs::write(&nca, ENCRYPTED_HEADER).unwrap();
+
+        let keys = read_keys(
+            dir.path(),
+            &[
+                ("header_key", header_key().as_slice()),
+                ("master_key_04", &[0; 16][..]),
+            ],
+        );
+        assert!(assert_key_generation(&nca, &keys).is_ok());
+
+        let keys = read_keys(
+            dir.path(),
+            &[
+                ("header_key", header_key().as_slice()),
+                ("master_key_03", &[0; 16][..]),
+            ],
+        );
+        match assert_key_generation(&nca, &keys) {
+            Err(Error::KeyMissing(key)) => assert_eq!(key, "master_key_04"),
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn reads_no_rights_id_of_header_without_title_key() {
+        let dir = tempdir().unwrap();
+        let nca = dir.path().join("program.nca");
+        fs::write(&nca, ENCRYPTED_HEADER).unwrap();
+        let keys = read_keys(dir.path(), &[("header_key", header_key().as_slice())]);
+        assert_eq!(read_rights_id(&nca, &keys).unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_header_decrypted_with_wrong_key() {
+        let dir = tempdir().unwrap();
+        let nca = dir.path().join("program.nca");
+        fs::write(&nca, ENCRYPTED_HEADER).unwrap();
+        let keys = read_keys(dir.path(), &[("header_key", &[0xff; 32][..])]);
+        assert!(matches!(
+            assert_key_generation(&nca, &keys),
+            Err(Error::NcaHeaderInvalid(_))
+        ));
+    }
+}
@@ -0,0 +1,408 @@
+use crate::{error::ResultExt, Result};
+use aes::{
+    cipher::{generic_array::GenericArray, BlockEncrypt, KeyInit},
+    Aes128,
+};
+use std::{
+    cmp,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Cursor, Read, Write},
+    ops::RangeInclusive,
+    path::Path,
+};
+
+const HEADER_SIZE: u64 = 0x4000;
+const SECTION_MAGIC: &[u8] = b"NCZSECTN";
+const BLOCK_MAGIC: &[u8] = b"NCZBLOCK";
+const CHUNK_SIZE: u64 = 0x10000;
+const CRYPTO_TYPE_CTR: u64 = 3;
+const CRYPTO_TYPE_BKTR: u64 = 4;
+/// NCAs have at most four sections, but BKTR sections might be split up further.
+const MAX_SECTION_COUNT: u64 = 0x100;
+/// Block sizes, which are supported by the NCZ block format.
+const BLOCK_SIZE_EXPONENTS: RangeInclusive<u8> = 14..=32;
+
+struct Section {
+    offset: u64,
+    size: u64,
+    crypto_type: u64,
+    key: [u8; 16],
+    counter: [u8; 16],
+}
+
+impl Section {
+    fn read(reader: &mut impl Read) -> io::Result<Self> {
+        let offset = read_u64(reader)?;
+        let size = read_u64(reader)?;
+        let crypto_type = read_u64(reader)?;
+        read_u64(reader)?;
+        let mut key = [0; 16];
+        reader.read_exact(&mut key)?;
+        let mut counter = [0; 16];
+        reader.read_exact(&mut counter)?;
+        Ok(Self {
+            offset,
+            size,
+            crypto_type,
+            key,
+            counter,
+        })
+    }
+}
+
+/// Decompresses all NCZ files within the given directory into NCA files, so that they can
+/// be extracted with hactool.
+pub fn decompress_ncz_dir(dir: &Path) -> Result<()> {
+    for dir_entry in fs::read_dir(dir).with_path(dir)? {
+        let ncz = dir_entry.with_path(dir)?.path();
+        if ncz.extension().map(|ext| ext.eq_ignore_ascii_case("ncz")) == Some(true) {
+            decompress_ncz(&ncz, &ncz.with_extension("nca")).with_path(&ncz)?;
+            fs::remove_file(&ncz).with_path(&ncz)?;
+        }
+    }
+    Ok(())
+}
+
+/// Restores the original NCA from a zstd compressed NCZ, whose data behind the first 0x4000
+/// bytes is stored decrypted, so it is encrypted again with the keys of its sections.
+pub fn decompress_ncz(ncz: &Path, nca: &Path) -> Result<()> {
+    let mut reader = BufReader::new(File::open(ncz)?);
+    let mut writer = BufWriter::new(File::create(nca)?);
+
+    let mut header = vec![0; HEADER_SIZE as usize];
+    reader.read_exact(&mut header)?;
+    writer.write_all(&header)?;
+
+    let mut magic = [0; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic[..] != SECTION_MAGIC {
+        return Err(invalid_data("NCZ section header missing").into());
+    }
+    let section_count = read_u64(&mut reader)?;
+    if section_count > MAX_SECTION_COUNT {
+        return Err(invalid_data("NCZ section table too large").into());
+    }
+    let sections = (0..section_count)
+        .map(|_| Section::read(&mut reader))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    reader.read_exact(&mut magic)?;
+    let mut decompressor: Box<dyn Read> = if &magic[..] == BLOCK_MAGIC {
+        Box::new(BlockDecompressor::new(reader)?)
+    } else {
+        Box::new(zstd::stream::read::Decoder::new(
+            Cursor::new(magic).chain(reader),
+        )?)
+    };
+
+    let mut buf = vec![0; CHUNK_SIZE as usize];
+    for section in sections.iter() {
+        let cipher = Aes128::new(GenericArray::from_slice(&section.key));
+        let mut position = cmp::max(section.offset, HEADER_SIZE);
+        let end = section
+            .offset
+            .checked_add(section.size)
+            .ok_or_else(|| invalid_data("NCZ section out of bounds"))?;
+        while position < end {
+            let chunk_size = cmp::min(CHUNK_SIZE, end - position) as usize;
+            let chunk = &mut buf[..chunk_size];
+            decompressor.read_exact(chunk)?;
+            if section.crypto_type == CRYPTO_TYPE_CTR || section.crypto_type == CRYPTO_TYPE_BKTR {
+                apply_ctr(&cipher, &section.counter, position, chunk);
+            }
+            writer.write_all(chunk)?;
+            position += chunk_size as u64;
+        }
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Applies AES-128-CTR to data at the given offset of a section.
+pub fn apply_ctr(cipher: &Aes128, counter: &[u8; 16], offset: u64, data: &mut [u8]) {
+    let mut block_index = offset / 16;
+    let mut skip = (offset % 16) as usize;
+    let mut i = 0;
+    while i < data.len() {
+        let mut key_stream = GenericArray::clone_from_slice(counter);
+        key_stream[8..].copy_from_slice(&block_index.to_be_bytes());
+        cipher.encrypt_block(&mut key_stream);
+        for k in key_stream.iter().skip(skip) {
+            if i >= data.len() {
+                break;
+            }
+            data[i] ^= k;
+            i += 1;
+        }
+        skip = 0;
+        block_index += 1;
+    }
+}
+
+/// Reader for NCZs, which have been compressed in independent blocks.
+struct BlockDecompressor<R> {
+    reader: R,
+    block_size: u64,
+    remaining: u64,
+    compressed_sizes: Vec<u32>,
+    index: usize,
+    block: Cursor<Vec<u8>>,
+}
+
+impl<R: Read> BlockDecompressor<R> {
+    fn new(mut reader: R) -> io::Result<Self> {
+        let mut header = [0; 4];
+        reader.read_exact(&mut header)?;
+        let block_size_exponent = header[3];
+        if !BLOCK_SIZE_EXPONENTS.contains(&block_size_exponent) {
+            return Err(invalid_data("NCZ block size unsupported"));
+        }
+        let block_size = 1u64 << block_size_exponent;
+        let block_count = read_u32(&mut reader)?;
+        let decompressed_size = read_u64(&mut reader)?;
+        if decompressed_size > (block_count as u64).saturating_mul(block_size) {
+            return Err(invalid_data("NCZ block table too small"));
+        }
+        let compressed_sizes = (0..block_count)
+            .map(|_| read_u32(&mut reader))
+            .collect::<io::Result<Vec<_>>>()?;
+        Ok(Self {
+            reader,
+            block_size,
+            remaining: decompressed_size,
+            compressed_sizes,
+            index: 0,
+            block: Cursor::new(vec![]),
+        })
+    }
+}
+
+impl<R: Read> Read for BlockDecompressor<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.block.position() as usize == self.block.get_ref().len() {
+            let compressed_size = match self.compressed_sizes.get(self.index) {
+                Some(compressed_size) => *compressed_size as u64,
+                None => return Ok(0),
+            };
+            let decompressed_size = cmp::min(self.block_size, self.remaining);
+            if compressed_size > decompressed_size {
+                return Err(invalid_data("NCZ block larger than its content"));
+            }
+            let mut data = vec![0; compressed_size as usize];
+            self.reader.read_exact(&mut data)?;
+            // blocks, which could not be compressed, are stored as is
+            if compressed_size < decompressed_size {
+                data = zstd::stream::decode_all(&data[..])?;
+            }
+            if data.len() as u64 != decompressed_size {
+                return Err(invalid_data("NCZ block size mismatch"));
+            }
+            self.remaining -= decompressed_size;
+            self.index += 1;
+            self.block = Cursor::new(data);
+        }
+        self.block.read(buf)
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha2::{Digest, Sha256};
+    use tempfile::tempdir;
+
+    const NCA_SIZE: usize = 0x1c000;
+    /// End of the encrypted section, behind which the data is incompressible.
+    const CTR_END: usize = 0x16000;
+    const BLOCK_SIZE_EXPONENT: u8 = 14;
+    /// SHA-256 of the restored NCA, whose first section has been encrypted with an
+    /// independent AES-128-CTR implementation.
+    const NCA_SHA256: &str = "432aea3975ffc62bb776c51e4657e2a5509e84603e9de98d9308559be7e5a1f9";
+    const NOISE: &[u8] = include_bytes!("../tests/fixtures/noise.bin");
+
+    fn key() -> [u8; 16] {
+        let mut key = [0; 16];
+        key.iter_mut().zip(0x40..).for_each(|(k, i)| *k = i);
+        key
+    }
+
+    fn counter() -> [u8; 16] {
+        let mut counter = [0; 16];
+        counter[..8]
+            .iter_mut()
+            .zip(0x60..)
+            .for_each(|(c, i)| *c = i);
+        counter
+    }
+
+    /// The decrypted NCA, which is stored in an NCZ.
+    fn plain_nca() -> Vec<u8> {
+        let mut nca: Vec<u8> = (0..CTR_END).map(|i| ((i * 31) ^ (i >> 9)) as u8).collect();
+        nca.extend(&NOISE[..NCA_SIZE - CTR_END]);
+        nca
+    }
+
+    fn section_table(sections: &[(u64, u64, u64)]) -> Vec<u8> {
+        let mut table = SECTION_MAGIC.to_vec();
+        table.extend((sections.len() as u64).to_le_bytes());
+        for (offset, size, crypto_type) in sections {
+            table.extend(offset.to_le_bytes());
+            table.extend(size.to_le_bytes());
+            table.extend(crypto_type.to_le_bytes());
+            table.extend(0u64.to_le_bytes());
+            table.extend(key());
+            table.extend(counter());
+        }
+        table
+    }
+
+    /// Sections, where the first one starts within the header like in real NCAs.
+    fn sections() -> Vec<(u64, u64, u64)> {
+        vec![
+            (0xc00, CTR_END as u64 - 0xc00, CRYPTO_TYPE_CTR),
+            (CTR_END as u64, (NCA_SIZE - CTR_END) as u64, 1),
+        ]
+    }
+
+    fn solid_ncz() -> Vec<u8> {
+        let nca = plain_nca();
+        let mut ncz = nca[..HEADER_SIZE as usize].to_vec();
+        ncz.extend(section_table(&sections()));
+        ncz.extend(zstd::encode_all(&nca[HEADER_SIZE as usize..], 0).unwrap());
+        ncz
+    }
+
+    fn block_ncz() -> Vec<u8> {
+        let nca = plain_nca();
+        let data = &nca[HEADER_SIZE as usize..];
+        let blocks: Vec<_> = data
+            .chunks(1 << BLOCK_SIZE_EXPONENT)
+            .map(|block| {
+                let compressed = zstd::encode_all(block, 0).unwrap();
+                if compressed.len() < block.len() {
+                    compressed
+                } else {
+                    block.to_vec()
+                }
+            })
+            .collect();
+        // the incompressible tail has to be stored as is
+        assert_eq!(blocks.last().unwrap().len(), 1 << BLOCK_SIZE_EXPONENT);
+
+        let mut ncz = nca[..HEADER_SIZE as usize].to_vec();
+        ncz.extend(section_table(&sections()));
+        ncz.extend(BLOCK_MAGIC);
+        ncz.extend([2, 1, 0, BLOCK_SIZE_EXPONENT]);
+        ncz.extend((blocks.len() as u32).to_le_bytes());
+        ncz.extend((data.len() as u64).to_le_bytes());
+        for block in blocks.iter() {
+            ncz.extend((block.len() as u32).to_le_bytes());
+        }
+        blocks.iter().for_each(|block| ncz.extend(block));
+        ncz
+    }
+
+    fn decompress(ncz: &[u8]) -> Result<Vec<u8>> {
+        let dir = tempdir().unwrap();
+        let ncz_path = dir.path().join("program.ncz");
+        let nca_path = dir.path().join("program.nca");
+        fs::write(&ncz_path, ncz).unwrap();
+        decompress_ncz(&ncz_path, &nca_path)?;
+        Ok(fs::read(&nca_path).unwrap())
+    }
+
+    fn assert_restored(nca: &[u8]) {
+        let plain = plain_nca();
+        assert_eq!(nca.len(), NCA_SIZE);
+        assert_eq!(nca[..HEADER_SIZE as usize], plain[..HEADER_SIZE as usize]);
+        assert_ne!(
+            nca[HEADER_SIZE as usize..CTR_END],
+            plain[HEADER_SIZE as usize..CTR_END]
+        );
+        assert_eq!(nca[CTR_END..], plain[CTR_END..]);
+        assert_eq!(hex::encode(Sha256::digest(nca)), NCA_SHA256);
+    }
+
+    #[test]
+    fn restores_solid_ncz() {
+        assert_restored(&decompress(&solid_ncz()).unwrap());
+    }
+
+    #[test]
+    fn restores_block_ncz() {
+        assert_restored(&decompress(&block_ncz()).unwrap());
+    }
+
+    #[test]
+    fn rejects_truncated_section_table() {
+        let ncz = solid_ncz();
+        let table_end = HEADER_SIZE as usize + 16 + 64 + 32;
+        assert!(decompress(&ncz[..table_end]).is_err());
+        assert!(decompress(&ncz[..HEADER_SIZE as usize + 4]).is_err());
+    }
+
+    #[test]
+    fn rejects_oversized_section_table() {
+        for section_count in [MAX_SECTION_COUNT + 1, u64::MAX] {
+            let mut ncz = plain_nca()[..HEADER_SIZE as usize].to_vec();
+            ncz.extend(SECTION_MAGIC);
+            ncz.extend(section_count.to_le_bytes());
+            assert!(decompress(&ncz).is_err());
+        }
+    }
+
+    #[test]
+    fn rejects_sections_out_of_bounds() {
+        let nca = plain_nca();
+        let mut ncz = nca[..HEADER_SIZE as usize].to_vec();
+        ncz.extend(section_table(&[(u64::MAX - 8, 16, CRYPTO_TYPE_CTR)]));
+        ncz.extend(zstd::encode_all(&nca[HEADER_SIZE as usize..], 0).unwrap());
+        assert!(decompress(&ncz).is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_data() {
+        let ncz = solid_ncz();
+        assert!(decompress(&ncz[..ncz.len() - 100]).is_err());
+        let ncz = block_ncz();
+        assert!(decompress(&ncz[..ncz.len() - 100]).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_block_tables() {
+        let block_header_offset = HEADER_SIZE as usize + 16 + 2 * 64 + BLOCK_MAGIC.len();
+
+        let mut ncz = block_ncz();
+        ncz[block_header_offset + 3] = 63;
+        assert!(decompress(&ncz).is_err());
+
+        // the first compressed block claims to be larger than a block
+        let mut ncz = block_ncz();
+        let first_size = block_header_offset + 4 + 4 + 8;
+        ncz[first_size..first_size + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(decompress(&ncz).is_err());
+
+        // the blocks cannot hold the decompressed size
+        let mut ncz = block_ncz();
+        ncz[block_header_offset + 8..block_header_offset + 16]
+            .copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(decompress(&ncz).is_err());
+    }
+}
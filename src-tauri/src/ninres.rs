@@ -1,79 +1,172 @@
-use crate::Result;
+use crate::{encoding::AssetRules, error::Error, tar_writer::TarEntry, Result};
 
-use image::{
-    codecs::png::{CompressionType, FilterType, PngEncoder},
-    DynamicImage, ImageBuffer, ImageEncoder,
-};
+use image::{DynamicImage, ImageBuffer, RgbaImage};
 use ninres::{Bfres, EmbeddedFile, NinRes, NinResFile, Sarc};
-use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use rayon::iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
 use std::{
     cmp,
-    io::Cursor,
-    path::{self, PathBuf},
-    sync::RwLock,
+    collections::BTreeMap,
+    ffi::OsStr,
+    path::{self, Path, PathBuf},
+    sync::Mutex,
 };
-use tar::Builder;
 
-pub fn bundle_ninres(
+/// Receives the number of images, which are encoded.
+pub trait EncodeProgress: Sync {
+    /// Adds images, once their texture has been decoded.
+    fn add_images(&self, count: u64);
+
+    /// Records encoded images.
+    fn encoded(&self, count: u64) -> Result<()>;
+}
+
+/// Where extracted images are encoded and collected.
+struct ImageSink<'a> {
+    entries: Mutex<Vec<TarEntry>>,
+    mtime: u64,
+    rules: &'a AssetRules,
+    progress: &'a dyn EncodeProgress,
+}
+
+/// Extracts and encodes all textures of an asset file sorted by their paths, since images are
+/// encoded in parallel. Files, which are no ninres files, have no textures.
+pub fn encode_assets(
+    file_data: &[u8],
+    rules: &AssetRules,
+    mtime: u64,
+    progress: &dyn EncodeProgress,
+) -> Result<Vec<TarEntry>> {
+    let file = match file_data.as_ninres() {
+        Ok(file) => file,
+        Err(_) => return Ok(vec![]),
+    };
+    let sink = ImageSink {
+        entries: Mutex::new(vec![]),
+        mtime,
+        rules,
+        progress,
+    };
+    let path = PathBuf::from("ninres");
+    match &file {
+        NinResFile::Bfres(bfres) => {
+            extract_bfres(bfres, &sink, path.clone(), path)?;
+        }
+        NinResFile::Sarc(sarc) => {
+            extract_sarc(sarc, &sink, path.clone(), path)?;
+        }
+    }
+    let mut entries = sink.entries.into_inner().unwrap();
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(entries)
+}
+
+/// Decodes the first mip of all textures within a ninres file, keyed by their path within
+/// the file, the texture name and the texture index.
+pub fn decode_textures(
     file: &NinResFile,
-    builder: &RwLock<tar::Builder<Cursor<Vec<u8>>>>,
     path: PathBuf,
-    mtime: u64,
-) -> Result<()> {
+    textures: &mut BTreeMap<String, RgbaImage>,
+) {
     match file {
         NinResFile::Bfres(bfres) => {
-            extract_bfres(bfres, builder, path.clone(), path, mtime)?;
+            for file in bfres.get_embedded_files().iter() {
+                match file {
+                    EmbeddedFile::BNTX(bntx) => {
+                        for texture in bntx.get_textures().iter() {
+                            for (tex_count, mips) in texture.get_texture_data().iter().enumerate() {
+                                let width = cmp::max(1, texture.width);
+                                let height = cmp::max(1, texture.height);
+                                if let Some(image) = mips.iter().next().and_then(|mip| {
+                                    ImageBuffer::from_raw(width, height, mip.clone())
+                                }) {
+                                    let name = format!(
+                                        "{}/{}_{}",
+                                        path.to_string_lossy().replace('\\', "/"),
+                                        texture.get_name(),
+                                        tex_count
+                                    );
+                                    textures.insert(name, image);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
         }
         NinResFile::Sarc(sarc) => {
-            extract_sarc(sarc, builder, path.clone(), path, mtime)?;
+            for sfat in sarc.get_sfat_nodes() {
+                if let Some(sfat_path) = sfat.get_path() {
+                    let data = if let Some(data) = sfat.get_data_decompressed() {
+                        data
+                    } else {
+                        sfat.get_data()
+                    };
+                    if let Ok(file) = data.as_ninres() {
+                        decode_textures(&file, path.join(sfat_path), textures);
+                    }
+                }
+            }
         }
     }
-    Ok(())
 }
 
 fn extract_bfres(
     bfres: &Bfres,
-    builder: &RwLock<tar::Builder<Cursor<Vec<u8>>>>,
+    sink: &ImageSink,
     out_path: PathBuf,
     base_path: PathBuf,
-    mtime: u64,
 ) -> Result<()> {
     for file in bfres.get_embedded_files().iter() {
         match file {
             EmbeddedFile::BNTX(bntx) => {
-                for texture in bntx.get_textures().iter() {
-                    let texture_name = texture.get_name();
-                    if texture_name.starts_with("WU_") {
-                        continue;
-                    }
-                    for (tex_count, mips) in texture.get_texture_data().iter().enumerate() {
-                        if let Some(mip) = mips.iter().next() {
-                            let width = cmp::max(1, texture.width);
-                            let height = cmp::max(1, texture.height);
-                            let buf = if let Some(image) =
-                                ImageBuffer::from_raw(width, height, mip.clone())
-                            {
-                                image
-                            } else {
-                                continue;
-                            };
-                            let image = DynamicImage::ImageRgba8(buf);
-
-                            let path_diff = out_path.strip_prefix(&base_path).unwrap();
-                            let mut file_name = path_diff
-                                .to_string_lossy()
-                                .replace("output", "")
-                                .replace(".Nin_NX_NVN", "")
-                                .replace(path::MAIN_SEPARATOR, "_")
-                                .replace("Model_", "");
-
-                            if texture_name.contains("_Field_")
-                                && !texture_name.contains("_Field_anime_")
-                            {
-                                (0..48u32)
-                                    .into_par_iter()
-                                    .map(|y| -> Result<()> {
-                                        for x in 0..16 {
+                bntx.get_textures()
+                    .par_iter()
+                    .map(|texture| -> Result<()> {
+                        let texture_name = texture.get_name();
+                        if texture_name.starts_with("WU_") {
+                            return Ok(());
+                        }
+                        for (tex_count, mips) in texture.get_texture_data().iter().enumerate() {
+                            if let Some(mip) = mips.iter().next() {
+                                let width = cmp::max(1, texture.width);
+                                let height = cmp::max(1, texture.height);
+                                let buf = if let Some(image) =
+                                    ImageBuffer::from_raw(width, height, mip.clone())
+                                {
+                                    image
+                                } else {
+                                    continue;
+                                };
+                                let image = DynamicImage::ImageRgba8(buf);
+
+                                let path_diff =
+                                    out_path.strip_prefix(&base_path).map_err(|_| {
+                                        Error::NinRes(format!(
+                                            "{} is not within {}",
+                                            out_path.display(),
+                                            base_path.display()
+                                        ))
+                                    })?;
+                                let mut file_name = path_diff
+                                    .to_string_lossy()
+                                    .replace("output", "")
+                                    .replace(".Nin_NX_NVN", "")
+                                    .replace(path::MAIN_SEPARATOR, "_")
+                                    .replace("Model_", "");
+
+                                let is_field = texture_name.contains("_Field_")
+                                    && !texture_name.contains("_Field_anime_");
+                                // every texture and tile is written opaque and transparent
+                                sink.progress.add_images(if is_field {
+                                    2 + 2 * 48 * 16
+                                } else {
+                                    2
+                                });
+                                if is_field {
+                                    (0..48 * 16u32)
+                                        .into_par_iter()
+                                        .map(|tile| -> Result<()> {
+                                            let (x, y) = (tile % 16, tile / 16);
                                             let image = image.crop_imm(x * 16, y * 16, 16, 16);
 
                                             let transparency_bytes: Vec<_> = image
@@ -86,7 +179,7 @@ fn extract_bfres(
                                             let transparent_bytes =
                                                 vec![0; transparency_bytes.len()];
                                             if transparency_bytes == transparent_bytes {
-                                                continue;
+                                                return sink.progress.encoded(2);
                                             }
 
                                             let mut file_name = file_name.clone();
@@ -95,15 +188,15 @@ fn extract_bfres(
                                                 "{}_{}_{}.png",
                                                 texture.get_name(),
                                                 tex_count,
-                                                x + 16 * y
+                                                tile
                                             ));
-                                            write_image(&image, &file_name, builder, mtime, None)?;
+                                            write_image(&image, &file_name, sink, None)?;
 
                                             transparent_file_name.push_str(&format!(
                                                 "0{}_{}_{}.png",
                                                 texture.get_name(),
                                                 tex_count,
-                                                x + 16 * y
+                                                tile
                                             ));
                                             let mut bytes = image.clone().into_bytes();
                                             for b in (3..bytes.len()).step_by(4) {
@@ -112,54 +205,51 @@ fn extract_bfres(
                                             write_image(
                                                 &image,
                                                 &transparent_file_name,
-                                                builder,
-                                                mtime,
+                                                sink,
                                                 Some(bytes),
-                                            )?;
-                                        }
-                                        Ok(())
-                                    })
-                                    .collect::<Result<()>>()?;
-                            }
-                            let mut transparent_file_name = file_name.clone();
-                            file_name.push_str(&format!(
-                                "{}_{}.png",
-                                texture.get_name(),
-                                tex_count
-                            ));
-                            write_image(&image, &file_name, builder, mtime, None)?;
-
-                            transparent_file_name.push_str(&format!(
-                                "0{}_{}.png",
-                                texture.get_name(),
-                                tex_count,
-                            ));
-                            let mut bytes = image.clone().into_bytes();
-                            for b in (3..bytes.len()).step_by(4) {
-                                bytes[b] = 192;
+                                            )
+                                        })
+                                        .collect::<Result<()>>()?;
+                                }
+                                let mut transparent_file_name = file_name.clone();
+                                file_name.push_str(&format!(
+                                    "{}_{}.png",
+                                    texture.get_name(),
+                                    tex_count
+                                ));
+                                write_image(&image, &file_name, sink, None)?;
+
+                                transparent_file_name.push_str(&format!(
+                                    "0{}_{}.png",
+                                    texture.get_name(),
+                                    tex_count,
+                                ));
+                                let mut bytes = image.clone().into_bytes();
+                                for b in (3..bytes.len()).step_by(4) {
+                                    bytes[b] = 192;
+                                }
+                                write_image(&image, &transparent_file_name, sink, Some(bytes))?;
                             }
-                            write_image(
-                                &image,
-                                &transparent_file_name,
-                                builder,
-                                mtime,
-                                Some(bytes),
-                            )?;
                         }
-                    }
-                }
+                        Ok(())
+                    })
+                    .collect::<Result<()>>()?;
             }
         }
     }
     Ok(())
 }
 
+fn file_stem(path: &Path) -> Result<&OsStr> {
+    path.file_stem()
+        .ok_or_else(|| Error::NinRes(format!("SARC entry without name: {}", path.display())))
+}
+
 fn extract_sarc(
     sarc: &Sarc,
-    builder: &RwLock<tar::Builder<Cursor<Vec<u8>>>>,
+    sink: &ImageSink,
     out_path: PathBuf,
     base_path: PathBuf,
-    mtime: u64,
 ) -> Result<()> {
     sarc.get_sfat_nodes()
         .into_par_iter()
@@ -180,14 +270,14 @@ fn extract_sarc(
                         NinResFile::Bfres(bfres) => {
                             let mut path0 = path.clone();
                             path0.pop();
-                            path0.push(path.file_stem().unwrap());
-                            extract_bfres(bfres, builder, path0, base_path.clone(), mtime)?;
+                            path0.push(file_stem(&path)?);
+                            extract_bfres(bfres, sink, path0, base_path.clone())?;
                         }
                         NinResFile::Sarc(sarc) => {
                             let mut path0 = path.clone();
                             path0.pop();
-                            path0.push(path.file_stem().unwrap());
-                            extract_sarc(sarc, builder, path0, base_path.clone(), mtime)?;
+                            path0.push(file_stem(&path)?);
+                            extract_sarc(sarc, sink, path0, base_path.clone())?;
                         }
                     }
                 }
@@ -198,35 +288,24 @@ fn extract_sarc(
     Ok(())
 }
 
+/// Encodes an image with the encoding selected by the asset rules and collects it.
+///
+/// File names are given with a `.png` extension, which is replaced according to the encoding.
 fn write_image(
     image: &DynamicImage,
     file_name: &str,
-    builder: &RwLock<Builder<Cursor<Vec<u8>>>>,
-    mtime: u64,
+    sink: &ImageSink,
     bytes: Option<Vec<u8>>,
 ) -> Result<()> {
-    let mut image_data = vec![];
-    let encoder =
-        PngEncoder::new_with_quality(&mut image_data, CompressionType::Best, FilterType::NoFilter);
-    if let Some(bytes) = bytes {
-        encoder.write_image(&bytes[..], image.width(), image.height(), image.color())?;
-    } else {
-        encoder.write_image(
-            image.as_bytes(),
-            image.width(),
-            image.height(),
-            image.color(),
-        )?;
-    }
-
-    let mut header = tar::Header::new_gnu();
-    header.set_size(image_data.len() as u64);
-    header.set_mode(0o644);
-    header.set_mtime(mtime);
-    header.set_cksum();
-    builder
-        .write()
+    let (path, image_data) = sink.rules.encode(
+        file_name,
+        bytes.as_deref().unwrap_or_else(|| image.as_bytes()),
+        image.width(),
+        image.height(),
+    )?;
+    sink.entries
+        .lock()
         .unwrap()
-        .append_data(&mut header, file_name, &image_data[..])?;
-    Ok(())
+        .push(TarEntry::file(path, image_data, sink.mtime));
+    sink.progress.encoded(1)
 }
@@ -0,0 +1,247 @@
+use crate::{error::ResultExt, manifest::MANIFEST_NAME, Result};
+use flate2::{write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+const DEFAULT_FILE_STEM: &str = "shroom_kingdom_assets";
+const INDEX_NAME: &str = "index.json";
+const WEB_INDEX_VERSION: u32 = 1;
+
+/// Extensions, which are replaced when switching the output format of a path.
+const KNOWN_EXTENSIONS: [&str; 5] = [".tar.gz", ".tar.zst", ".tgz", ".tar", ".zip"];
+
+/// Formats in which a bundle can be saved.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    #[default]
+    Tar,
+    TarGz,
+    TarZst,
+    Zip,
+    Directory,
+    /// Directory tree of content-addressed files with an index, which maps logical names to
+    /// the hashed files, so that they can be cached by a CDN forever.
+    Web,
+}
+
+#[derive(Serialize)]
+struct WebIndex {
+    version: u32,
+    /// Maps logical names to content-addressed files relative to the index.
+    files: BTreeMap<String, String>,
+}
+
+impl OutputFormat {
+    /// The file extension of this format, or `None` if it is written as a directory tree.
+    pub fn extension(&self) -> Option<&'static str> {
+        match self {
+            OutputFormat::Tar => Some("tar"),
+            OutputFormat::TarGz => Some("tar.gz"),
+            OutputFormat::TarZst => Some("tar.zst"),
+            OutputFormat::Zip => Some("zip"),
+            OutputFormat::Directory | OutputFormat::Web => None,
+        }
+    }
+
+    /// Replaces the extension of a selected path with the extension of this format.
+    pub fn output_path(&self, mut path: PathBuf) -> PathBuf {
+        let extension = match self.extension() {
+            Some(extension) => extension,
+            None => return path,
+        };
+        let file_name = path
+            .file_name()
+            .map(|file_name| file_name.to_string_lossy().to_string())
+            .unwrap_or_else(|| DEFAULT_FILE_STEM.to_string());
+        let file_stem = KNOWN_EXTENSIONS
+            .iter()
+            .find_map(|known_extension| {
+                let stem_len = file_name.len().checked_sub(known_extension.len())?;
+                if file_name
+                    .get(stem_len..)?
+                    .eq_ignore_ascii_case(known_extension)
+                {
+                    file_name.get(..stem_len)
+                } else {
+                    None
+                }
+            })
+            .unwrap_or(&file_name);
+        path.set_file_name(format!("{}.{}", file_stem, extension));
+        path
+    }
+
+    /// Writes a bundle, which is always built as an uncompressed tar, in this format.
+    ///
+    /// The manifest is only kept in tar formats, since only they can be loaded as base
+    /// bundles.
+    pub fn write(&self, bundle_data: &[u8], path: &Path) -> Result<()> {
+        match self {
+            OutputFormat::Tar => fs::write(path, bundle_data).with_path(path),
+            OutputFormat::TarGz => {
+                let file = File::create(path).with_path(path)?;
+                let mut encoder = GzEncoder::new(BufWriter::new(file), Compression::default());
+                encoder.write_all(bundle_data).with_path(path)?;
+                encoder.finish().with_path(path)?.flush().with_path(path)
+            }
+            OutputFormat::TarZst => {
+                let file = File::create(path).with_path(path)?;
+                let mut encoder =
+                    zstd::stream::write::Encoder::new(BufWriter::new(file), 0).with_path(path)?;
+                encoder.write_all(bundle_data).with_path(path)?;
+                encoder.finish().with_path(path)?.flush().with_path(path)
+            }
+            OutputFormat::Zip => write_zip(bundle_data, path),
+            OutputFormat::Directory => write_dir(bundle_data, path),
+            OutputFormat::Web => write_web(bundle_data, path),
+        }
+    }
+}
+
+fn write_zip(bundle_data: &[u8], path: &Path) -> Result<()> {
+    let file = File::create(path).with_path(path)?;
+    let mut writer = ZipWriter::new(BufWriter::new(file));
+    // images are compressed already
+    let options = FileOptions::default().compression_method(CompressionMethod::Stored);
+    for entry in tar::Archive::new(bundle_data).entries().with_path(path)? {
+        let mut entry = entry.with_path(path)?;
+        let name = entry
+            .path()
+            .with_path(path)?
+            .to_string_lossy()
+            .replace('\\', "/");
+        if name == MANIFEST_NAME {
+            continue;
+        }
+        writer.start_file(name, options).with_path(path)?;
+        io::copy(&mut entry, &mut writer).with_path(path)?;
+    }
+    writer.finish().with_path(path)?.flush().with_path(path)
+}
+
+fn write_dir(bundle_data: &[u8], path: &Path) -> Result<()> {
+    fs::create_dir_all(path).with_path(path)?;
+    for entry in tar::Archive::new(bundle_data).entries().with_path(path)? {
+        let mut entry = entry.with_path(path)?;
+        if entry.path().with_path(path)? == Path::new(MANIFEST_NAME) {
+            continue;
+        }
+        entry.unpack_in(path).with_path(path)?;
+    }
+    Ok(())
+}
+
+/// Writes all entries of a bundle as `assets/<hash prefix>/<hash>.<extension>` and an
+/// `index.json` mapping the logical names to these files.
+///
+/// Files of previous exports are kept, so that clients, which still use an old index, do not
+/// break.
+fn write_web(bundle_data: &[u8], path: &Path) -> Result<()> {
+    let mut index = BTreeMap::new();
+    for entry in tar::Archive::new(bundle_data).entries().with_path(path)? {
+        let mut entry = entry.with_path(path)?;
+        let name = entry
+            .path()
+            .with_path(path)?
+            .to_string_lossy()
+            .replace('\\', "/");
+        if name == MANIFEST_NAME {
+            continue;
+        }
+        let mut data = Vec::with_capacity(entry.size() as usize);
+        entry.read_to_end(&mut data).with_path(path)?;
+
+        let hash = hex::encode(Sha256::digest(&data));
+        let mut hashed_name = format!("assets/{}/{}", &hash[..2], hash);
+        if let Some(extension) = Path::new(&name).extension() {
+            hashed_name.push('.');
+            hashed_name.push_str(&extension.to_string_lossy());
+        }
+        let hashed_path = path.join(&hashed_name);
+        if !hashed_path.exists() {
+            if let Some(dir) = hashed_path.parent() {
+                fs::create_dir_all(dir).with_path(dir)?;
+            }
+            fs::write(&hashed_path, &data).with_path(&hashed_path)?;
+        }
+        index.insert(name, hashed_name);
+    }
+
+    let index_path = path.join(INDEX_NAME);
+    let index = serde_json::to_vec_pretty(&WebIndex {
+        version: WEB_INDEX_VERSION,
+        files: index,
+    })
+    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    .with_path(&index_path)?;
+    fs::write(&index_path, index).with_path(&index_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILE_FORMATS: [(OutputFormat, &str); 4] = [
+        (OutputFormat::Tar, "tar"),
+        (OutputFormat::TarGz, "tar.gz"),
+        (OutputFormat::TarZst, "tar.zst"),
+        (OutputFormat::Zip, "zip"),
+    ];
+
+    #[test]
+    fn appends_extension_to_path_without_extension() {
+        for (format, extension) in FILE_FORMATS {
+            assert_eq!(
+                format.output_path(PathBuf::from("out/assets")),
+                PathBuf::from(format!("out/assets.{}", extension))
+            );
+        }
+    }
+
+    #[test]
+    fn replaces_known_extensions_regardless_of_case() {
+        for (format, extension) in FILE_FORMATS {
+            for known_extension in KNOWN_EXTENSIONS.iter().chain(&[".TAR.GZ", ".Zip"]) {
+                assert_eq!(
+                    format.output_path(PathBuf::from(format!("out/assets{}", known_extension))),
+                    PathBuf::from(format!("out/assets.{}", extension))
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn keeps_unknown_extensions() {
+        for (format, extension) in FILE_FORMATS {
+            assert_eq!(
+                format.output_path(PathBuf::from("out/assets.v2")),
+                PathBuf::from(format!("out/assets.v2.{}", extension))
+            );
+        }
+    }
+
+    #[test]
+    fn uses_default_file_stem_for_path_without_file_name() {
+        assert_eq!(
+            OutputFormat::Zip.output_path(PathBuf::from("/")),
+            PathBuf::from(format!("/{}.zip", DEFAULT_FILE_STEM))
+        );
+    }
+
+    #[test]
+    fn keeps_path_of_directory_formats() {
+        for format in [OutputFormat::Directory, OutputFormat::Web] {
+            for path in ["out/assets", "out/assets.tar.gz", "out/assets.zip"] {
+                assert_eq!(format.output_path(PathBuf::from(path)), PathBuf::from(path));
+            }
+        }
+    }
+}
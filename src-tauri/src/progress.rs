@@ -0,0 +1,208 @@
+use crate::{ninres::EncodeProgress, Result};
+use serde::Serialize;
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tauri::Window;
+
+/// Minimum interval between two progress events, so that the UI is not flooded with events
+/// while thousands of images are encoded.
+pub const EMIT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Phases of a run, each of which measures its work in its own unit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    /// Decrypting game dumps, measured in bytes.
+    Decrypt,
+    /// Bundling asset files, measured in files.
+    Bundle,
+    /// Encoding images, measured in images.
+    Encode,
+    /// Comparing mods with the base game, measured in mods.
+    Compare,
+    /// Writing the finished bundle.
+    Finish,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct PhaseProgress {
+    pub phase: Phase,
+    /// Share of this phase in the total work.
+    pub weight: f64,
+    pub done: u64,
+    /// Total work of this phase, which might still grow, e.g. while textures are decoded.
+    pub total: u64,
+    /// Units of work per second since the phase started.
+    pub throughput: Option<f64>,
+    #[serde(skip)]
+    started: Option<Instant>,
+}
+
+impl PhaseProgress {
+    fn fraction(&self) -> f64 {
+        if self.total == 0 {
+            0.
+        } else {
+            (self.done as f64 / self.total as f64).min(1.)
+        }
+    }
+}
+
+/// Payload of the `extract_progress` event.
+#[derive(Clone, Debug, Serialize)]
+pub struct ProgressEvent {
+    /// The phase, which has advanced last.
+    pub phase: Phase,
+    /// Weighted progress of all phases between 0 and 100.
+    pub percent: f64,
+    pub elapsed_secs: f64,
+    /// Estimated remaining time, which is only known after some work has been done.
+    pub eta_secs: Option<f64>,
+    pub phases: Vec<PhaseProgress>,
+}
+
+struct ProgressState {
+    phases: Vec<PhaseProgress>,
+    percent: f64,
+    last_emit: Option<Instant>,
+}
+
+/// Progress of a run, which is measured by the work done in each phase.
+///
+/// All updates happen under a single lock, so that concurrent updates are never lost.
+pub struct Progress {
+    window: Mutex<Window>,
+    started: Instant,
+    state: Mutex<ProgressState>,
+}
+
+impl Progress {
+    /// Creates the progress of a run with the given phases and their weights.
+    pub fn new(window: Window, phases: &[(Phase, f64)]) -> Self {
+        let phases = phases
+            .iter()
+            .map(|(phase, weight)| PhaseProgress {
+                phase: *phase,
+                weight: *weight,
+                done: 0,
+                total: 0,
+                throughput: None,
+                started: None,
+            })
+            .collect();
+        Self {
+            window: Mutex::new(window),
+            started: Instant::now(),
+            state: Mutex::new(ProgressState {
+                phases,
+                percent: 0.,
+                last_emit: None,
+            }),
+        }
+    }
+
+    /// Adds work to a phase, once it is known.
+    pub fn add_total(&self, phase: Phase, total: u64) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(phase) = state.phases.iter_mut().find(|p| p.phase == phase) {
+            phase.total += total;
+        }
+    }
+
+    /// Starts measuring the throughput of a phase, when its work begins, so that the time
+    /// until its first work is done is included.
+    pub fn start(&self, phase: Phase) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(phase) = state.phases.iter_mut().find(|p| p.phase == phase) {
+            phase.started.get_or_insert_with(Instant::now);
+        }
+    }
+
+    /// Records finished work of a phase.
+    pub fn advance(&self, phase: Phase, done: u64) -> Result<()> {
+        self.update(phase, false, |phase| phase.done += done)
+    }
+
+    /// Marks all work of a phase as done, e.g. if it has been skipped due to caching.
+    pub fn complete(&self, phase: Phase) -> Result<()> {
+        self.update(phase, true, |phase| {
+            phase.total = phase.total.max(1);
+            phase.done = phase.total;
+        })
+    }
+
+    fn update(
+        &self,
+        phase: Phase,
+        force_emit: bool,
+        update: impl FnOnce(&mut PhaseProgress),
+    ) -> Result<()> {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        if let Some(phase) = state.phases.iter_mut().find(|p| p.phase == phase) {
+            let started = *phase.started.get_or_insert(now);
+            update(phase);
+            let secs = now.duration_since(started).as_secs_f64();
+            if secs > 0. {
+                phase.throughput = Some(phase.done as f64 / secs);
+            }
+        }
+
+        let weights: f64 = state.phases.iter().map(|phase| phase.weight).sum();
+        let percent = if weights > 0. {
+            state
+                .phases
+                .iter()
+                .map(|phase| phase.weight * phase.fraction())
+                .sum::<f64>()
+                / weights
+                * 100.
+        } else {
+            0.
+        };
+        // totals grow while textures are decoded, which must not move the progress backwards
+        state.percent = state.percent.max(percent);
+
+        if !force_emit
+            && state
+                .last_emit
+                .map(|last_emit| now.duration_since(last_emit) < EMIT_INTERVAL)
+                .unwrap_or_default()
+        {
+            return Ok(());
+        }
+        state.last_emit = Some(now);
+
+        let elapsed_secs = now.duration_since(self.started).as_secs_f64();
+        let eta_secs = if state.percent > 0. {
+            Some(elapsed_secs * (100. - state.percent) / state.percent)
+        } else {
+            None
+        };
+        let event = ProgressEvent {
+            phase,
+            percent: state.percent,
+            elapsed_secs,
+            eta_secs,
+            phases: state.phases.clone(),
+        };
+        // the state stays locked while emitting, so that events are emitted in order
+        self.window
+            .lock()
+            .unwrap()
+            .emit("extract_progress", event)?;
+        Ok(())
+    }
+}
+
+impl EncodeProgress for Progress {
+    fn add_images(&self, count: u64) {
+        self.add_total(Phase::Encode, count);
+    }
+
+    fn encoded(&self, count: u64) -> Result<()> {
+        self.advance(Phase::Encode, count)
+    }
+}
@@ -0,0 +1,232 @@
+use std::{
+    convert::TryInto,
+    io,
+    path::{Path, PathBuf},
+};
+
+const HEADER_SIZE: usize = 0x50;
+const EMPTY_ENTRY: u32 = 0xffff_ffff;
+const DIR_ENTRY_SIZE: usize = 0x18;
+const FILE_ENTRY_SIZE: usize = 0x20;
+/// Larger tables are rejected, since even complete games have much smaller ones.
+const MAX_TABLE_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Lists all files of a romfs by reading its directory and file tables.
+///
+/// `read_at` reads data at an offset of the romfs, which has the given size. Tables are
+/// read in ascending order, so that the romfs can be read from a stream.
+pub fn list_romfs(
+    size: u64,
+    mut read_at: impl FnMut(u64, usize) -> io::Result<Vec<u8>>,
+) -> io::Result<Vec<PathBuf>> {
+    let header = read_at(0, HEADER_SIZE)?;
+    let field = |index: usize| u64::from_le_bytes(header[index * 8..][..8].try_into().unwrap());
+    let dir_table = (field(3), field(4));
+    let file_table = (field(7), field(8));
+
+    let mut tables = [dir_table, file_table];
+    tables.sort_unstable();
+    let mut data = vec![];
+    for (offset, table_size) in tables.iter().copied() {
+        if table_size > MAX_TABLE_SIZE || offset.saturating_add(table_size) > size {
+            return Err(invalid_data("romfs table out of bounds"));
+        }
+        data.push(read_at(offset, table_size as usize)?);
+    }
+    if tables[0] != dir_table {
+        data.reverse();
+    }
+    let (dirs, files) = (&data[0], &data[1]);
+
+    let mut paths = vec![];
+    // every entry is visited once, unless the tables contain cycles
+    let mut remaining = dirs.len() / DIR_ENTRY_SIZE + files.len() / FILE_ENTRY_SIZE;
+    let mut pending = vec![(0, PathBuf::new())];
+    while let Some((dir_offset, dir_path)) = pending.pop() {
+        let dir = entry(dirs, dir_offset, DIR_ENTRY_SIZE)?;
+        let mut child = field_u32(dir, 0x8);
+        while child != EMPTY_ENTRY {
+            let child_dir = entry(dirs, child, DIR_ENTRY_SIZE)?;
+            pending.push((child, dir_path.join(entry_name(child_dir, DIR_ENTRY_SIZE)?)));
+            child = field_u32(child_dir, 0x4);
+            remaining = visit(remaining)?;
+        }
+        let mut file = field_u32(dir, 0xc);
+        while file != EMPTY_ENTRY {
+            let file_entry = entry(files, file, FILE_ENTRY_SIZE)?;
+            paths.push(dir_path.join(entry_name(file_entry, FILE_ENTRY_SIZE)?));
+            file = field_u32(file_entry, 0x4);
+            remaining = visit(remaining)?;
+        }
+    }
+    Ok(paths)
+}
+
+/// Returns the table data starting at an entry, which has to contain at least its fields.
+fn entry(table: &[u8], offset: u32, fields_size: usize) -> io::Result<&[u8]> {
+    table
+        .get(offset as usize..)
+        .filter(|entry| entry.len() >= fields_size)
+        .ok_or_else(|| invalid_data("romfs entry out of bounds"))
+}
+
+fn entry_name(entry: &[u8], fields_size: usize) -> io::Result<&Path> {
+    let name_size = field_u32(entry, fields_size - 4) as usize;
+    let name = entry
+        .get(fields_size..fields_size + name_size)
+        .ok_or_else(|| invalid_data("romfs entry name out of bounds"))?;
+    let name = std::str::from_utf8(name).map_err(|_| invalid_data("romfs entry name invalid"))?;
+    // names must not escape their directory
+    if name.is_empty() || name.contains(&['/', '\\'][..]) || name == "." || name == ".." {
+        return Err(invalid_data("romfs entry name invalid"));
+    }
+    Ok(Path::new(name))
+}
+
+fn field_u32(entry: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(entry[offset..offset + 4].try_into().unwrap())
+}
+
+fn visit(remaining: usize) -> io::Result<usize> {
+    remaining
+        .checked_sub(1)
+        .ok_or_else(|| invalid_data("romfs tables contain a cycle"))
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// Builds a romfs, which only consists of its header and tables, since file data is
+    /// never read.
+    pub fn romfs(dirs: &[(&str, &[&str])]) -> Vec<u8> {
+        let mut dir_table = vec![];
+        let mut file_table = vec![];
+        let dir_offsets: Vec<u32> = {
+            let mut offset = DIR_ENTRY_SIZE as u32;
+            dirs.iter()
+                .map(|(name, _)| {
+                    let dir_offset = offset;
+                    offset += (DIR_ENTRY_SIZE + padded(name.len())) as u32;
+                    dir_offset
+                })
+                .collect()
+        };
+
+        let first_child = dir_offsets.first().copied().unwrap_or(EMPTY_ENTRY);
+        push_dir(&mut dir_table, "", first_child, EMPTY_ENTRY, EMPTY_ENTRY);
+        for (index, (name, file_names)) in dirs.iter().enumerate() {
+            let sibling = dir_offsets.get(index + 1).copied().unwrap_or(EMPTY_ENTRY);
+            let first_file = if file_names.is_empty() {
+                EMPTY_ENTRY
+            } else {
+                file_table.len() as u32
+            };
+            push_dir(&mut dir_table, name, EMPTY_ENTRY, sibling, first_file);
+            for (index, file_name) in file_names.iter().enumerate() {
+                let sibling = if index + 1 < file_names.len() {
+                    (file_table.len() + FILE_ENTRY_SIZE + padded(file_name.len())) as u32
+                } else {
+                    EMPTY_ENTRY
+                };
+                push_file(&mut file_table, file_name, sibling);
+            }
+        }
+
+        let dir_offset = 0x200u64;
+        let file_offset = dir_offset + dir_table.len() as u64;
+        let mut data = vec![0; dir_offset as usize];
+        let fields = [
+            HEADER_SIZE as u64,
+            dir_offset,
+            0,
+            dir_offset,
+            dir_table.len() as u64,
+            file_offset,
+            0,
+            file_offset,
+            file_table.len() as u64,
+            0x200,
+        ];
+        for (index, field) in fields.iter().enumerate() {
+            data[index * 8..][..8].copy_from_slice(&field.to_le_bytes());
+        }
+        data.extend(dir_table);
+        data.extend(file_table);
+        data
+    }
+
+    fn padded(len: usize) -> usize {
+        (len + 3) & !3
+    }
+
+    fn push_dir(table: &mut Vec<u8>, name: &str, child: u32, sibling: u32, file: u32) {
+        for field in [0, sibling, child, file, EMPTY_ENTRY, name.len() as u32] {
+            table.extend(field.to_le_bytes());
+        }
+        push_name(table, name);
+    }
+
+    fn push_file(table: &mut Vec<u8>, name: &str, sibling: u32) {
+        table.extend(0u32.to_le_bytes());
+        table.extend(sibling.to_le_bytes());
+        table.extend([0; 16]);
+        table.extend(EMPTY_ENTRY.to_le_bytes());
+        table.extend((name.len() as u32).to_le_bytes());
+        push_name(table, name);
+    }
+
+    fn push_name(table: &mut Vec<u8>, name: &str) {
+        table.extend(name.as_bytes());
+        table.resize(table.len() + padded(name.len()) - name.len(), 0);
+    }
+
+    fn list(data: &[u8]) -> io::Result<Vec<PathBuf>> {
+        list_romfs(data.len() as u64, |offset, len| {
+            data.get(offset as usize..offset as usize + len)
+                .map(<[u8]>::to_vec)
+                .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))
+        })
+    }
+
+    #[test]
+    fn lists_files_of_all_dirs() {
+        let data = romfs(&[
+            (
+                "Model",
+                &[
+                    "MW_Field_plain.Nin_NX_NVN.zs",
+                    "MW_DV_plain_V.Nin_NX_NVN.zs",
+                ],
+            ),
+            ("Pack", &["MW_Model.pack"]),
+        ]);
+        let mut paths = list(&data).unwrap();
+        paths.sort();
+        assert_eq!(
+            paths,
+            vec![
+                PathBuf::from("Model/MW_DV_plain_V.Nin_NX_NVN.zs"),
+                PathBuf::from("Model/MW_Field_plain.Nin_NX_NVN.zs"),
+                PathBuf::from("Pack/MW_Model.pack"),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_cycles_and_tables_out_of_bounds() {
+        let mut data = romfs(&[("Model", &["a.zs"])]);
+        // the file of the root dir is the file of the Model dir, whose sibling is itself
+        let file_table = 0x200 + 2 * DIR_ENTRY_SIZE + padded(5);
+        data[0x200 + 0xc..0x200 + 0x10].copy_from_slice(&0u32.to_le_bytes());
+        data[file_table + 4..file_table + 8].copy_from_slice(&0u32.to_le_bytes());
+        assert!(list(&data).is_err());
+
+        let data = romfs(&[("Model", &["a.zs"])]);
+        assert!(list(&data[..data.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,211 @@
+use crate::{
+    progress::{Phase, EMIT_INTERVAL},
+    Result,
+};
+use serde::Serialize;
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Instant, SystemTime},
+};
+use tauri::Window;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogLevel {
+    /// Output of sidecars and single files, which is only needed to track down bugs.
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl LogLevel {
+    fn label(&self) -> &'static str {
+        match self {
+            LogLevel::Debug => "DEBUG",
+            LogLevel::Info => "INFO",
+            LogLevel::Warn => "WARN",
+            LogLevel::Error => "ERROR",
+        }
+    }
+}
+
+/// Entry of the `extract_log` event, whose payload is a batch of entries.
+#[derive(Clone, Debug, Serialize)]
+pub struct LogEntry {
+    pub level: LogLevel,
+    pub phase: Option<Phase>,
+    /// The selected file, which the entry belongs to.
+    pub input: Option<PathBuf>,
+    pub message: String,
+    /// Milliseconds since the Unix epoch.
+    pub timestamp: u64,
+}
+
+/// The entries of the current or last run, which are shared with the app state, so that
+/// they can be exported or fetched by the log view at any time.
+pub type LogEntries = Arc<Mutex<Vec<LogEntry>>>;
+
+/// Collects the log of a single extraction or comparison and streams it to the UI.
+///
+/// Entries are streamed in batches, so that the UI is not flooded with events while hactool
+/// prints thousands of lines. Debug entries are only streamed, if the UI shows them.
+pub struct RunLog {
+    entries: LogEntries,
+    details: Arc<AtomicBool>,
+    stream: Mutex<LogStream>,
+}
+
+struct LogStream {
+    window: Window,
+    /// Number of entries, which have been streamed or skipped.
+    sent: usize,
+    last_emit: Option<Instant>,
+}
+
+impl RunLog {
+    /// Starts the log of a new run, which replaces the given entries of the last run.
+    pub fn new(window: Window, entries: LogEntries, details: Arc<AtomicBool>) -> Self {
+        entries.lock().unwrap().clear();
+        Self {
+            entries,
+            details,
+            stream: Mutex::new(LogStream {
+                window,
+                sent: 0,
+                last_emit: None,
+            }),
+        }
+    }
+
+    pub fn log(
+        &self,
+        level: LogLevel,
+        phase: Option<Phase>,
+        input: Option<&Path>,
+        message: impl Into<String>,
+    ) -> Result<()> {
+        let entry = LogEntry {
+            level,
+            phase,
+            input: input.map(Path::to_path_buf),
+            message: message.into(),
+            timestamp: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as u64,
+        };
+        // the stream stays locked while emitting, so that the UI receives entries in order
+        let mut stream = self.stream.lock().unwrap();
+        let mut entries = self.entries.lock().unwrap();
+        entries.push(entry);
+        // debug entries are the bulk of the log, so only they wait for the next batch
+        let now = Instant::now();
+        if level == LogLevel::Debug
+            && stream
+                .last_emit
+                .map(|last_emit| now.duration_since(last_emit) < EMIT_INTERVAL)
+                .unwrap_or_default()
+        {
+            return Ok(());
+        }
+        stream.last_emit = Some(now);
+        self.emit(&mut stream, &entries)
+    }
+
+    /// Streams all entries, which are still held back, at the end of a run.
+    pub fn flush(&self) -> Result<()> {
+        let mut stream = self.stream.lock().unwrap();
+        let entries = self.entries.lock().unwrap();
+        self.emit(&mut stream, &entries)
+    }
+
+    fn emit(&self, stream: &mut LogStream, entries: &[LogEntry]) -> Result<()> {
+        let details = self.details.load(Ordering::SeqCst);
+        let batch: Vec<_> = entries[stream.sent..]
+            .iter()
+            .filter(|entry| details || entry.level != LogLevel::Debug)
+            .collect();
+        stream.sent = entries.len();
+        if !batch.is_empty() {
+            stream.window.emit("extract_log", batch)?;
+        }
+        Ok(())
+    }
+
+    pub fn debug(
+        &self,
+        phase: Option<Phase>,
+        input: Option<&Path>,
+        message: impl Into<String>,
+    ) -> Result<()> {
+        self.log(LogLevel::Debug, phase, input, message)
+    }
+
+    pub fn info(
+        &self,
+        phase: Option<Phase>,
+        input: Option<&Path>,
+        message: impl Into<String>,
+    ) -> Result<()> {
+        self.log(LogLevel::Info, phase, input, message)
+    }
+
+    pub fn warn(
+        &self,
+        phase: Option<Phase>,
+        input: Option<&Path>,
+        message: impl Into<String>,
+    ) -> Result<()> {
+        self.log(LogLevel::Warn, phase, input, message)
+    }
+
+    pub fn error(
+        &self,
+        phase: Option<Phase>,
+        input: Option<&Path>,
+        message: impl Into<String>,
+    ) -> Result<()> {
+        self.log(LogLevel::Error, phase, input, message)
+    }
+}
+
+/// Formats a log as text, which can be attached to bug reports.
+///
+/// Entries are timed relative to the first entry, whose absolute time is given in the header.
+pub fn format_log(entries: &[LogEntry]) -> String {
+    let start = entries
+        .first()
+        .map(|entry| entry.timestamp)
+        .unwrap_or_default();
+    let mut text = format!(
+        "{} {}, started at {} ms since the Unix epoch\n",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        start
+    );
+    for entry in entries {
+        let elapsed = entry.timestamp.saturating_sub(start);
+        write!(
+            text,
+            "[{:>5}.{:03}s] {:<5}",
+            elapsed / 1000,
+            elapsed % 1000,
+            entry.level.label()
+        )
+        .unwrap();
+        if let Some(phase) = entry.phase {
+            write!(text, " {:?}", phase).unwrap();
+        }
+        if let Some(input) = &entry.input {
+            write!(text, " {}", input.to_string_lossy()).unwrap();
+        }
+        writeln!(text, ": {}", entry.message).unwrap();
+    }
+    text
+}
@@ -0,0 +1,59 @@
+use crate::{encoding::AssetRule, error::ResultExt, output::OutputFormat, Result};
+use serde::{Deserialize, Serialize};
+use std::{fs, io, path::PathBuf};
+
+const CONFIG_DIR_NAME: &str = "shroom-kingdom-asset-extractor";
+const SETTINGS_FILE_NAME: &str = "settings.json";
+
+/// Settings and the last file selection, which are persisted across app restarts.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub prod_key: Option<PathBuf>,
+    pub output_dir: Option<PathBuf>,
+    pub output_format: OutputFormat,
+    pub selected_files: Vec<PathBuf>,
+    pub asset_rules: Vec<AssetRule>,
+    pub optimize_png: bool,
+}
+
+impl Settings {
+    /// Loads the settings from the platform config dir, falling back to the default settings,
+    /// if they have never been saved.
+    pub fn load() -> Result<Self> {
+        let path = match settings_path() {
+            Some(path) if path.is_file() => path,
+            _ => return Ok(Self::default()),
+        };
+        let data = fs::read_to_string(&path).with_path(&path)?;
+        serde_json::from_str(&data)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+            .with_path(&path)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = match settings_path() {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).with_path(dir)?;
+        }
+        let data = serde_json::to_string_pretty(self)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+            .with_path(&path)?;
+        fs::write(&path, data).with_path(&path)
+    }
+
+    /// Deletes the persisted settings.
+    pub fn reset() -> Result<()> {
+        match settings_path() {
+            Some(path) if path.is_file() => fs::remove_file(&path).with_path(&path),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn settings_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join(CONFIG_DIR_NAME).join(SETTINGS_FILE_NAME))
+}
@@ -0,0 +1,66 @@
+use crate::{error::Error, progress::Phase, run_log::RunLog, Result};
+use std::{
+    path::Path,
+    time::{Duration, Instant},
+};
+use tauri::api::process::{Command, CommandEvent};
+use tokio::time::timeout;
+
+/// Interval, in which a running sidecar is polled, e.g. to measure the files it has written.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Runs a bundled sidecar binary to completion, while processing the given input.
+///
+/// Stdout is logged line by line, while stderr is captured and returned as part of an
+/// [`Error::Sidecar`], if the process does not exit successfully. `on_poll` is called
+/// regularly, while the sidecar runs, even if it does not print anything.
+pub async fn run_sidecar(
+    log: &RunLog,
+    input: &Path,
+    tool: &str,
+    args: Vec<String>,
+    mut on_poll: impl FnMut() -> Result<()>,
+) -> Result<()> {
+    let (mut rx_sidecar, _) = Command::new_sidecar(tool)?.args(args).spawn()?;
+
+    let mut stderr = vec![];
+    let mut code = None;
+    let mut last_poll = Instant::now();
+    loop {
+        let event = match timeout(POLL_INTERVAL, rx_sidecar.recv()).await {
+            Ok(Some(event)) => Some(event),
+            Ok(None) => break,
+            Err(_) => None,
+        };
+        if last_poll.elapsed() >= POLL_INTERVAL {
+            on_poll()?;
+            last_poll = Instant::now();
+        }
+        match event {
+            Some(CommandEvent::Stdout(line)) => {
+                log.debug(Some(Phase::Decrypt), Some(input), line.trim_end())?;
+            }
+            Some(CommandEvent::Stderr(line)) => {
+                stderr.push(line);
+            }
+            Some(CommandEvent::Error(err)) => {
+                stderr.push(err);
+            }
+            Some(CommandEvent::Terminated(payload)) => {
+                code = payload.code;
+                break;
+            }
+            _ => {}
+        }
+    }
+
+    if code == Some(0) {
+        Ok(())
+    } else {
+        Err(Error::Sidecar {
+            tool: tool.to_string(),
+            code,
+            stderr: stderr.join("\n"),
+        })
+    }
+}
@@ -0,0 +1,230 @@
+use crate::{error::Error, Result};
+use crossbeam_channel::{bounded, Sender};
+use std::{
+    collections::BTreeMap,
+    io,
+    path::PathBuf,
+    sync::{Arc, Condvar, Mutex},
+    thread::{self, JoinHandle},
+};
+
+/// A file, which is sent to a [`TarWriter`].
+pub struct TarEntry {
+    pub path: PathBuf,
+    pub header: tar::Header,
+    pub data: Vec<u8>,
+}
+
+impl TarEntry {
+    /// Creates a regular file entry.
+    pub fn file(path: PathBuf, data: Vec<u8>, mtime: u64) -> Self {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(mtime);
+        header.set_cksum();
+        Self { path, header, data }
+    }
+}
+
+/// Entries, which are written at the position given by the sequence number.
+struct TarRecord {
+    sequence: usize,
+    entries: Vec<TarEntry>,
+}
+
+/// Builds a tar on a dedicated thread, which writes records in the order of their sequence
+/// numbers, so every sequence number has to be sent exactly once.
+pub struct TarWriter {
+    sender: TarSender,
+    thread: JoinHandle<Result<tar::Builder<Vec<u8>>>>,
+}
+
+impl TarWriter {
+    pub fn spawn(capacity: usize) -> Self {
+        let (sender, receiver) = bounded::<TarRecord>(capacity);
+        let written = Arc::new(Written {
+            next_sequence: Mutex::new(Some(0)),
+            changed: Condvar::new(),
+        });
+        let stopped = StopGuard(written.clone());
+        let thread = thread::spawn(move || {
+            let written = &stopped.0;
+            let mut builder = tar::Builder::new(vec![]);
+            let mut pending = BTreeMap::new();
+            let mut next_sequence = 0;
+            for record in receiver {
+                pending.insert(record.sequence, record.entries);
+                let before = next_sequence;
+                while let Some(entries) = pending.remove(&next_sequence) {
+                    append_entries(&mut builder, entries)?;
+                    next_sequence += 1;
+                }
+                if next_sequence != before {
+                    written.update(Some(next_sequence));
+                }
+            }
+            // producers, which panicked, never send their records, so the held back records
+            // are written in order without them
+            for entries in pending.into_values() {
+                append_entries(&mut builder, entries)?;
+            }
+            Ok(builder)
+        });
+        Self {
+            sender: TarSender {
+                sender,
+                written,
+                capacity,
+            },
+            thread,
+        }
+    }
+
+    pub fn sender(&self) -> &TarSender {
+        &self.sender
+    }
+
+    /// Waits until all sent records have been written and returns the unfinished builder.
+    pub fn finish(self) -> Result<tar::Builder<Vec<u8>>> {
+        drop(self.sender);
+        self.thread
+            .join()
+            .map_err(|_| Error::from(io::Error::new(io::ErrorKind::Other, "tar writer panicked")))?
+    }
+}
+
+/// Sends records to a [`TarWriter`] with their sequence numbers.
+pub struct TarSender {
+    sender: Sender<TarRecord>,
+    written: Arc<Written>,
+    capacity: usize,
+}
+
+impl TarSender {
+    /// Waits until a sequence number is less than `capacity` records ahead of the writer, so
+    /// it must only be called by the thread starting producers, but never by a producer.
+    pub fn reserve(&self, sequence: usize) -> Result<()> {
+        let mut written = self.written.next_sequence.lock().unwrap();
+        loop {
+            match *written {
+                Some(next_sequence) if sequence < next_sequence + self.capacity => return Ok(()),
+                Some(_) => written = self.written.changed.wait(written).unwrap(),
+                None => return Err(io::Error::from(io::ErrorKind::BrokenPipe).into()),
+            }
+        }
+    }
+
+    /// Sends entries, which are written after the entries of all lower sequence numbers.
+    pub fn send(&self, sequence: usize, entries: Vec<TarEntry>) -> Result<()> {
+        self.sender
+            .send(TarRecord { sequence, entries })
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe).into())
+    }
+}
+
+/// The progress of the writer thread, which producers wait for.
+struct Written {
+    /// The next sequence number to be written or `None`, if the writer has stopped.
+    next_sequence: Mutex<Option<usize>>,
+    changed: Condvar,
+}
+
+impl Written {
+    fn update(&self, next_sequence: Option<usize>) {
+        *self.next_sequence.lock().unwrap() = next_sequence;
+        self.changed.notify_all();
+    }
+}
+
+/// Marks the writer as stopped, when its thread ends, so that no producer waits forever.
+struct StopGuard(Arc<Written>);
+
+impl Drop for StopGuard {
+    fn drop(&mut self) {
+        self.0.update(None);
+    }
+}
+
+fn append_entries(builder: &mut tar::Builder<Vec<u8>>, entries: Vec<TarEntry>) -> Result<()> {
+    for mut entry in entries {
+        builder.append_data(&mut entry.header, &entry.path, &entry.data[..])?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        sync::atomic::{AtomicBool, Ordering},
+        time::Duration,
+    };
+
+    fn entry(name: &str) -> TarEntry {
+        TarEntry::file(PathBuf::from(name), name.as_bytes().to_vec(), 0)
+    }
+
+    fn names(builder: tar::Builder<Vec<u8>>) -> Vec<String> {
+        let data = builder.into_inner().unwrap();
+        tar::Archive::new(&data[..])
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                entry
+                    .unwrap()
+                    .path()
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn writes_records_in_sequence_order() {
+        let writer = TarWriter::spawn(4);
+        for (sequence, name) in [(2, "c"), (0, "a"), (3, "d"), (1, "b")] {
+            writer.sender().send(sequence, vec![entry(name)]).unwrap();
+        }
+        // skipped assets send empty records
+        writer.sender().send(4, vec![]).unwrap();
+        writer.sender().send(5, vec![entry("e")]).unwrap();
+        assert_eq!(names(writer.finish().unwrap()), ["a", "b", "c", "d", "e"]);
+    }
+
+    #[test]
+    fn reserve_waits_for_records_before_the_window() {
+        let writer = Arc::new(TarWriter::spawn(2));
+        writer.sender().reserve(0).unwrap();
+        writer.sender().reserve(1).unwrap();
+
+        let sent = Arc::new(AtomicBool::new(false));
+        let producer = {
+            let writer = writer.clone();
+            let sent = sent.clone();
+            thread::spawn(move || {
+                writer.sender().send(1, vec![entry("b")]).unwrap();
+                thread::sleep(Duration::from_millis(50));
+                sent.store(true, Ordering::SeqCst);
+                writer.sender().send(0, vec![entry("a")]).unwrap();
+            })
+        };
+        writer.sender().reserve(2).unwrap();
+        assert!(sent.load(Ordering::SeqCst));
+        producer.join().unwrap();
+
+        writer.sender().send(2, vec![entry("c")]).unwrap();
+        let writer = Arc::try_unwrap(writer).ok().unwrap();
+        assert_eq!(names(writer.finish().unwrap()), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn reserve_fails_when_writer_stopped() {
+        let writer = TarWriter::spawn(2);
+        // paths escaping the archive are rejected by the tar builder
+        writer.sender().send(0, vec![entry("../a")]).unwrap();
+        assert!(writer.sender().reserve(4).is_err());
+        assert!(writer.finish().is_err());
+    }
+}
@@ -0,0 +1,86 @@
+use crate::{error::ResultExt, Result};
+use std::{collections::HashMap, convert::TryInto, ffi::OsStr, fs, path::Path};
+
+const TITLE_KEY_OFFSET: usize = 0x40;
+const TITLE_KEY_TYPE_OFFSET: usize = 0x141;
+const RIGHTS_ID_OFFSET: usize = 0x160;
+const TITLE_KEY_TYPE_COMMON: u8 = 0;
+
+/// The encrypted title key of a common ticket, which decrypts the NCAs of its rights ID.
+pub struct Ticket {
+    pub rights_id: [u8; 16],
+    pub title_key: [u8; 16],
+}
+
+impl Ticket {
+    /// Parses a ticket, unless it is personalized, since its title key is then encrypted for
+    /// a single console.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let signature_type = u32::from_le_bytes(data.get(..4)?.try_into().ok()?);
+        // the signature is padded, so that the ticket data is aligned to 0x40 bytes
+        let data_offset = match signature_type {
+            0x10000 | 0x10003 => 0x240,
+            0x10001 | 0x10004 => 0x140,
+            0x10002 | 0x10005 => 0x80,
+            _ => return None,
+        };
+        let data = data.get(data_offset..)?;
+        if *data.get(TITLE_KEY_TYPE_OFFSET)? != TITLE_KEY_TYPE_COMMON {
+            return None;
+        }
+        Some(Self {
+            rights_id: data
+                .get(RIGHTS_ID_OFFSET..RIGHTS_ID_OFFSET + 16)?
+                .try_into()
+                .ok()?,
+            title_key: data
+                .get(TITLE_KEY_OFFSET..TITLE_KEY_OFFSET + 16)?
+                .try_into()
+                .ok()?,
+        })
+    }
+}
+
+/// Reads the title keys of all common tickets within a directory by their rights ID.
+pub fn read_title_keys(dir: &Path) -> Result<HashMap<[u8; 16], [u8; 16]>> {
+    let mut title_keys = HashMap::new();
+    for dir_entry in fs::read_dir(dir).with_path(dir)? {
+        let path = dir_entry.with_path(dir)?.path();
+        if path.extension().and_then(OsStr::to_str) == Some("tik") {
+            if let Some(ticket) = Ticket::parse(&fs::read(&path).with_path(&path)?) {
+                title_keys.insert(ticket.rights_id, ticket.title_key);
+            }
+        }
+    }
+    Ok(title_keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ticket(signature_type: u32, data_offset: usize, title_key_type: u8) -> Vec<u8> {
+        let mut data = vec![0; data_offset + 0x180];
+        data[..4].copy_from_slice(&signature_type.to_le_bytes());
+        data[data_offset + TITLE_KEY_OFFSET..][..16].copy_from_slice(&[1; 16]);
+        data[data_offset + TITLE_KEY_TYPE_OFFSET] = title_key_type;
+        data[data_offset + RIGHTS_ID_OFFSET..][..16].copy_from_slice(&[2; 16]);
+        data
+    }
+
+    #[test]
+    fn parses_common_tickets_of_all_signature_types() {
+        for (signature_type, data_offset) in [(0x10000, 0x240), (0x10004, 0x140), (0x10005, 0x80)] {
+            let ticket = Ticket::parse(&ticket(signature_type, data_offset, 0)).unwrap();
+            assert_eq!(ticket.title_key, [1; 16]);
+            assert_eq!(ticket.rights_id, [2; 16]);
+        }
+    }
+
+    #[test]
+    fn skips_personalized_and_malformed_tickets() {
+        assert!(Ticket::parse(&ticket(0x10004, 0x140, 1)).is_none());
+        assert!(Ticket::parse(&ticket(0x20000, 0x140, 0)).is_none());
+        assert!(Ticket::parse(&ticket(0x10004, 0x140, 0)[..0x200]).is_none());
+    }
+}
@@ -1,126 +1,240 @@
-use crate::{increase_progress, Result};
-use std::{
-    fs::{read_dir, DirEntry},
-    path::Path,
-    sync::{Arc, RwLock},
+use crate::{
+    error::{Error, ResultExt},
+    keys::Keys,
+    nca::{assert_key_generation, read_rights_id},
+    ncz::decompress_ncz_dir,
+    progress::{Phase, Progress},
+    run_log::RunLog,
+    sidecar::run_sidecar,
+    ticket::read_title_keys,
+    Result,
 };
-use tauri::{
-    api::process::{Command, CommandEvent, TerminatedPayload},
-    async_runtime, Window,
+use std::{
+    ffi::OsStr,
+    fs::{self, read_dir, DirEntry},
+    path::{Path, PathBuf},
 };
+use tauri::Window;
 use tempfile::TempDir;
 
 #[allow(clippy::too_many_arguments)]
 pub async fn extract_xci(
     window: Window,
+    log: &RunLog,
     dir: &TempDir,
     romfs_dir: &Path,
     exefs_dir: &Path,
     file: &Path,
     prod_key: &Path,
-    progress: Arc<RwLock<f64>>,
-    max_progress: u32,
+    progress: &Progress,
     file_message: &str,
 ) -> Result<()> {
-    let (mut rx_sidecar, _) = Command::new_sidecar("hactool")?
-        .args(vec![
-            "--intype=xci",
-            "-k",
-            &prod_key.to_string_lossy(),
-            &format!("--securedir={}", dir.path().to_string_lossy()),
-            &file.to_string_lossy(),
-        ])
-        .spawn()?;
+    let file_size = fs::metadata(file).with_path(file)?.len();
+    let mut meter = OutputMeter::new(progress, &[dir.path()], file_size);
+    run_sidecar(
+        log,
+        file,
+        "hactool",
+        vec![
+            "--intype=xci".to_string(),
+            "-k".to_string(),
+            prod_key.to_string_lossy().to_string(),
+            format!("--securedir={}", dir.path().to_string_lossy()),
+            file.to_string_lossy().to_string(),
+        ],
+        || meter.poll(),
+    )
+    .await?;
+    meter.finish()?;
+    window.emit(
+        "extract_step",
+        &format!("{}\nExtracting bundled NCAs", file_message),
+    )?;
+
+    let mut meter = OutputMeter::new(progress, &[romfs_dir, exefs_dir], file_size);
+    extract_ncas(log, file, dir, romfs_dir, exefs_dir, prod_key, &mut meter).await?;
+    meter.finish()
+}
 
-    let (tx, mut rx) = async_runtime::channel(1);
-    {
-        let window = window.clone();
-        let progress = progress.clone();
-        let file_message = file_message.to_string();
-        async_runtime::spawn(async move {
-            while let Some(event) = rx_sidecar.recv().await {
-                if let CommandEvent::Stdout(line) = event {
-                    window.emit("extract_message", line).unwrap();
-                }
-            }
-            increase_progress(window.clone(), progress.clone(), max_progress).unwrap();
-            window
-                .emit(
-                    "extract_step",
-                    &format!("{}\nExtracting bundled NCAs", file_message),
-                )
-                .unwrap();
-            tx.send(CommandEvent::Terminated(TerminatedPayload {
-                code: Some(0),
-                signal: None,
-            }))
-            .await
-            .unwrap();
-        });
+#[allow(clippy::too_many_arguments)]
+pub async fn extract_nsp(
+    window: Window,
+    log: &RunLog,
+    dir: &TempDir,
+    romfs_dir: &Path,
+    exefs_dir: &Path,
+    file: &Path,
+    prod_key: &Path,
+    progress: &Progress,
+    file_message: &str,
+) -> Result<()> {
+    let file_size = fs::metadata(file).with_path(file)?.len();
+    run_sidecar(
+        log,
+        file,
+        "hactool",
+        vec![
+            "--intype=pfs0".to_string(),
+            "-k".to_string(),
+            prod_key.to_string_lossy().to_string(),
+            format!("--pfs0dir={}", dir.path().to_string_lossy()),
+            file.to_string_lossy().to_string(),
+        ],
+        // the NCAs of an NSP are only copied, but not decrypted
+        || Ok(()),
+    )
+    .await?;
+    window.emit(
+        "extract_step",
+        &format!("{}\nExtracting bundled NCAs", file_message),
+    )?;
+
+    let mut meter = OutputMeter::new(progress, &[romfs_dir, exefs_dir], file_size);
+    extract_ncas(log, file, dir, romfs_dir, exefs_dir, prod_key, &mut meter).await?;
+    meter.finish()
+}
+
+/// Credits the bytes, which hactool has written so far, to the decryption phase, so that
+/// the progress moves while hactool runs.
+///
+/// Written bytes only approximate the decrypted bytes, e.g. since only some NCAs contain a
+/// romfs, so they are capped at the bytes of one decryption pass and the rest is credited
+/// when the pass has finished.
+struct OutputMeter<'a> {
+    progress: &'a Progress,
+    dirs: Vec<PathBuf>,
+    credited: u64,
+    limit: u64,
+}
+
+impl<'a> OutputMeter<'a> {
+    fn new(progress: &'a Progress, dirs: &[&Path], limit: u64) -> Self {
+        Self {
+            progress,
+            dirs: dirs.iter().map(|dir| dir.to_path_buf()).collect(),
+            credited: 0,
+            limit,
+        }
     }
-    rx.recv().await;
 
+    fn poll(&mut self) -> Result<()> {
+        let written: u64 = self.dirs.iter().map(|dir| written_bytes(dir)).sum();
+        let written = written.min(self.limit);
+        if written > self.credited {
+            self.progress
+                .advance(Phase::Decrypt, written - self.credited)?;
+            self.credited = written;
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<()> {
+        self.progress
+            .advance(Phase::Decrypt, self.limit - self.credited)
+    }
+}
+
+/// Size of all files within a directory, which hactool is still writing to, so files
+/// vanishing in between are skipped.
+fn written_bytes(path: &Path) -> u64 {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => read_dir(path)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|dir_entry| written_bytes(&dir_entry.path()))
+            .sum(),
+        Ok(metadata) => metadata.len(),
+        Err(_) => 0,
+    }
+}
+
+/// Extracts the romfs and exefs of all NCAs within the given directory.
+///
+/// Compressed NCZs of NSZ and XCZ files are decompressed beforehand. NCAs with a rights ID
+/// are decrypted with the title key of their ticket, which eShop dumps contain.
+async fn extract_ncas(
+    log: &RunLog,
+    file: &Path,
+    dir: &TempDir,
+    romfs_dir: &Path,
+    exefs_dir: &Path,
+    prod_key: &Path,
+    meter: &mut OutputMeter<'_>,
+) -> Result<()> {
+    decompress_ncz_dir(dir.path())?;
+
+    let keys = Keys::read(prod_key)?;
+    let title_keys = read_title_keys(dir.path())?;
+    let mut ncas = vec![];
     for dir_entry in read_dir(dir.path())? {
         let dir_entry = dir_entry?;
-        let window = window.clone();
+        let path = dir_entry.path();
+        if path.extension().and_then(OsStr::to_str) == Some("nca") {
+            assert_key_generation(&path, &keys)?;
+            // hactool would only fail later with a generic error
+            let title_key = read_rights_id(&path, &keys)?
+                .map(|rights_id| {
+                    title_keys
+                        .get(&rights_id)
+                        .copied()
+                        .ok_or_else(|| Error::TitleKeyMissing(hex::encode(rights_id)))
+                })
+                .transpose()?;
+            ncas.push((dir_entry, title_key));
+        }
+    }
+
+    log.info(
+        Some(Phase::Decrypt),
+        Some(file),
+        format!("Extracting {} NCAs", ncas.len()),
+    )?;
+    for (dir_entry, title_key) in ncas.iter() {
         extract_nca(
-            window,
+            log,
+            file,
             dir.path(),
-            &dir_entry,
+            dir_entry,
+            *title_key,
             romfs_dir,
             exefs_dir,
             prod_key,
+            meter,
         )
         .await?;
     }
-    increase_progress(window.clone(), progress.clone(), max_progress)?;
 
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn extract_nca(
-    window: Window,
+    log: &RunLog,
+    file: &Path,
     dir: &Path,
     dir_entry: &DirEntry,
+    title_key: Option<[u8; 16]>,
     romfs_dir: &Path,
     exefs_dir: &Path,
     prod_key: &Path,
+    meter: &mut OutputMeter<'_>,
 ) -> Result<()> {
-    let (mut rx_sidecar, _) = Command::new_sidecar("hactool")
-        .expect("failed to create `hactool` binary command")
-        .args(vec![
-            "-x",
-            "-k",
-            &prod_key.to_string_lossy(),
-            &format!("--romfsdir={}", romfs_dir.to_string_lossy()),
-            &format!("--exefsdir={}", exefs_dir.to_string_lossy()),
-            &dir.to_path_buf()
-                .join(dir_entry.file_name())
-                .to_string_lossy(),
-        ])
-        .spawn()
-        .expect("Failed to spawn hactool");
-
-    let (tx, mut rx) = async_runtime::channel(1);
-    {
-        let window = window.clone();
-        async_runtime::spawn(async move {
-            while let Some(event) = rx_sidecar.recv().await {
-                if let CommandEvent::Stdout(line) = event {
-                    window
-                        .emit("extract_message", line)
-                        .expect("failed to emit event");
-                }
-            }
-            tx.send(CommandEvent::Terminated(TerminatedPayload {
-                code: Some(0),
-                signal: None,
-            }))
-            .await
-            .unwrap();
-        });
+    let mut args = vec![
+        "-x".to_string(),
+        "-k".to_string(),
+        prod_key.to_string_lossy().to_string(),
+        format!("--romfsdir={}", romfs_dir.to_string_lossy()),
+        format!("--exefsdir={}", exefs_dir.to_string_lossy()),
+    ];
+    if let Some(title_key) = title_key {
+        args.push(format!("--titlekey={}", hex::encode(title_key)));
     }
-    rx.recv().await;
-
-    Ok(())
+    args.push(
+        dir.to_path_buf()
+            .join(dir_entry.file_name())
+            .to_string_lossy()
+            .to_string(),
+    );
+    run_sidecar(log, file, "hactool", args, || meter.poll()).await
 }
@@ -7,41 +7,123 @@ import { getCurrent } from '@tauri-apps/api/window';
 
 import { AssetSelect } from './steps/asset-select';
 import { Intro } from './steps/intro';
+import { LogEntry } from './log-view';
+import { ModDiff } from './mod-diff-report';
 import { MultiStep } from './multistep';
 import { ExtractProgress } from './steps/extract-progress';
 
-export interface RequiredFilesMissingError {
-  RequiredFilesMissing: string[];
+export interface FoundKey {
+  path: string;
+  source: string;
+}
+
+export interface Conflict {
+  asset: string;
+  overridden: string;
+  by: string;
+}
+
+export interface InputCoverage {
+  input: string;
+  listed: boolean;
+  file_count: number;
+  required_files: string[];
+}
+
+export interface Coverage {
+  inputs: InputCoverage[];
+  suppliers: Record<string, string | null>;
+}
+
+export type OutputFormat =
+  | 'tar'
+  | 'tar_gz'
+  | 'tar_zst'
+  | 'zip'
+  | 'directory'
+  | 'web';
+
+export type ImageEncoding = 'png' | 'webp' | 'avif' | 'ktx2_rgba8';
+
+export interface AssetRule {
+  pattern: string;
+  encoding: ImageEncoding;
+}
+
+export interface Settings {
+  prod_key: string | null;
+  output_dir: string | null;
+  output_format: OutputFormat;
+  selected_files: string[];
+  asset_rules: AssetRule[];
+  optimize_png: boolean;
+}
+
+export type Phase = 'decrypt' | 'bundle' | 'encode' | 'compare' | 'finish';
+
+export interface PhaseProgress {
+  phase: Phase;
+  weight: number;
+  done: number;
+  total: number;
+  throughput: number | null;
+}
+
+export interface ProgressEvent {
+  phase: Phase;
+  percent: number;
+  elapsed_secs: number;
+  eta_secs: number | null;
+  phases: PhaseProgress[];
+}
+
+export interface AppError {
+  kind: string;
+  message: string;
+  path?: string;
+  // eslint-disable-next-line @typescript-eslint/no-explicit-any
+  details?: any;
 }
 
 export const App: FC = () => {
   const [loading, setLoading] = useState<boolean>(false);
-  const [keys, setKeys] = useState<string[]>([]);
+  const [keys, setKeys] = useState<FoundKey[]>([]);
   const [prodKey, setProdKey] = useState<string | null>(null);
   const [assetFiles, setAssetFiles] = useState<string[]>([]);
+  const [baseBundle, setBaseBundle] = useState<string | null>(null);
+  const [coverage, setCoverage] = useState<Coverage | null>(null);
   const [filesMissing, setFilesMissing] = useState<string[] | null>(null);
   const [extractProgress, setExtractProgress] = useState<number>(0);
-  const [extractError, setExtractError] = useState<Record<
-    string,
-    string
-  > | null>(null);
-  const [extractMessages, setExtractMessages] = useState<string>('');
+  const [progressEvent, setProgressEvent] = useState<ProgressEvent | null>(
+    null
+  );
+  const [extractError, setExtractError] = useState<AppError | null>(null);
+  const [baseBundleError, setBaseBundleError] = useState<string | null>(null);
+  const [logEntries, setLogEntries] = useState<LogEntry[]>([]);
+  const [logDetails, setLogDetails] = useState<boolean>(false);
   const [extractStep, setExtractStep] = useState<string>('');
+  const [modDiffs, setModDiffs] = useState<ModDiff[]>([]);
+  const [conflicts, setConflicts] = useState<Conflict[]>([]);
   const [bundleData, setBundleData] = useState<boolean>(false);
-  const messages = useRef(extractMessages);
-  let extractMessageTimeout: number | null = null;
+  const [outputFormat, setOutputFormat] = useState<OutputFormat>('tar');
+  const [assetRules, setAssetRules] = useState<AssetRule[]>([]);
+  const [optimizePng, setOptimizePng] = useState<boolean>(false);
+  const log = useRef<LogEntry[]>([]);
+  let logTimeout: number | null = null;
 
   useEffect(() => {
     const current = getCurrent();
     const progressListener = current.listen('extract_progress', event => {
-      setExtractProgress(event.payload as number);
+      const progress = event.payload as ProgressEvent;
+      setProgressEvent(progress);
+      setExtractProgress(progress.percent);
     });
-    const messageListener = current.listen('extract_message', event => {
-      messages.current = event.payload as string;
-      if (!extractMessageTimeout) {
-        extractMessageTimeout = setTimeout(() => {
-          setExtractMessages(messages.current);
-          extractMessageTimeout = null;
+    const logListener = current.listen('extract_log', event => {
+      log.current.push(...(event.payload as LogEntry[]));
+      if (!logTimeout) {
+        logTimeout = setTimeout(() => {
+          setLogEntries([...log.current]);
+          logTimeout = null;
         }, 500) as unknown as number;
       }
     });
@@ -51,7 +133,7 @@ export const App: FC = () => {
     return () => {
       (async () => {
         (await progressListener)();
-        (await messageListener)();
+        (await logListener)();
         (await stepListener)();
       })();
     };
@@ -60,7 +142,7 @@ export const App: FC = () => {
   useEffect(() => {
     const run = async () => {
       try {
-        const keys = await invoke<string[]>('find_keys');
+        const keys = await invoke<FoundKey[]>('find_keys');
         setKeys(keys);
       } catch (err) {
         console.error(err);
@@ -69,6 +151,26 @@ export const App: FC = () => {
     run();
   }, []);
 
+  useEffect(() => {
+    const run = async () => {
+      try {
+        const settings = await invoke<Settings>('load_settings');
+        setProdKey(settings.prod_key);
+        setOutputFormat(settings.output_format);
+        setAssetRules(settings.asset_rules);
+        setOptimizePng(settings.optimize_png);
+        setAssetFiles(settings.selected_files);
+        setCoverage(await invoke<Coverage>('get_coverage'));
+        if (settings.selected_files.length !== 0) {
+          await assertAddedFiles();
+        }
+      } catch (err) {
+        console.error(err);
+      }
+    };
+    run();
+  }, []);
+
   const handleSetProdKey = useCallback(
     (prodKey: string) => async () => {
       try {
@@ -90,35 +192,60 @@ export const App: FC = () => {
     }
   }, []);
 
-  const handleAddFiles = useCallback(async () => {
-    try {
-      const selectedFiles = await open({
-        multiple: true,
-        filters: [{ extensions: ['zip', '7z', 'xci'], name: '.zip,.7z,.xci' }]
-      });
-      const files = await invoke<string[]>('add_files', {
-        files: selectedFiles
-      });
-      setAssetFiles(files);
+  const handleAddFiles = useCallback(
+    (directory: boolean) => async () => {
+      try {
+        const selectedFiles = await open({
+          multiple: true,
+          directory,
+          filters: directory
+            ? undefined
+            : [
+                {
+                  extensions: [
+                    'zip',
+                    '7z',
+                    'rar',
+                    'tar',
+                    'gz',
+                    'tgz',
+                    'zst',
+                    'xci',
+                    'xcz',
+                    'nsp',
+                    'nsz'
+                  ],
+                  name: '.zip,.7z,.rar,.tar,.tar.gz,.tar.zst,.xci,.xcz,.nsp,.nsz'
+                }
+              ]
+        });
+        const files = await invoke<string[]>('add_files', {
+          files: selectedFiles
+        });
+        setAssetFiles(files);
+        setCoverage(await invoke<Coverage>('get_coverage'));
 
-      await invoke<string[]>('assert_added_files');
-      setFilesMissing(null);
-      // eslint-disable-next-line @typescript-eslint/no-explicit-any
-    } catch (err: any) {
-      if (err.RequiredFilesMissing) {
-        const filesMissingError = err as RequiredFilesMissingError;
-        setFilesMissing(filesMissingError.RequiredFilesMissing);
-      } else {
-        console.error(err);
+        await invoke<string[]>('assert_added_files');
+        setFilesMissing(null);
+        // eslint-disable-next-line @typescript-eslint/no-explicit-any
+      } catch (err: any) {
+        if (err.kind === 'required_files_missing') {
+          const filesMissingError = err as AppError;
+          setFilesMissing(filesMissingError.details as string[]);
+        } else {
+          console.error(err);
+        }
       }
-    }
-  }, []);
+    },
+    []
+  );
 
   const handleRemoveFile = useCallback(
     (fileName: string) => async () => {
       try {
         const files = await invoke<string[]>('remove_file', { fileName });
         setAssetFiles(files);
+        setCoverage(await invoke<Coverage>('get_coverage'));
 
         if (files.length !== 0) {
           await invoke<string[]>('assert_added_files');
@@ -126,9 +253,9 @@ export const App: FC = () => {
         setFilesMissing(null);
         // eslint-disable-next-line @typescript-eslint/no-explicit-any
       } catch (err: any) {
-        if (err.RequiredFilesMissing) {
-          const filesMissingError = err as RequiredFilesMissingError;
-          setFilesMissing(filesMissingError.RequiredFilesMissing);
+        if (err.kind === 'required_files_missing') {
+          const filesMissingError = err as AppError;
+          setFilesMissing(filesMissingError.details as string[]);
         } else {
           console.error(err);
         }
@@ -137,18 +264,164 @@ export const App: FC = () => {
     []
   );
 
+  const assertAddedFiles = useCallback(async () => {
+    try {
+      await invoke<string[]>('assert_added_files');
+      setFilesMissing(null);
+      // eslint-disable-next-line @typescript-eslint/no-explicit-any
+    } catch (err: any) {
+      if (err.kind === 'required_files_missing') {
+        const filesMissingError = err as AppError;
+        setFilesMissing(filesMissingError.details as string[]);
+      } else {
+        console.error(err);
+      }
+    }
+  }, []);
+
+  const handleLoadBaseBundle = useCallback(async () => {
+    try {
+      setBaseBundleError(null);
+      const baseBundle = await invoke<string>('load_base_bundle');
+      setBaseBundle(baseBundle);
+      await assertAddedFiles();
+    } catch (err) {
+      const appError = err as AppError;
+      if (appError.kind !== 'file_select_canceled') {
+        setBaseBundleError(appError.message);
+      }
+      console.error(err);
+    }
+  }, []);
+
+  const handleRemoveBaseBundle = useCallback(async () => {
+    try {
+      await invoke('remove_base_bundle');
+      setBaseBundle(null);
+      await assertAddedFiles();
+    } catch (err) {
+      console.error(err);
+    }
+  }, []);
+
+  const handleResetSettings = useCallback(async () => {
+    try {
+      await invoke('reset_settings');
+      setProdKey(null);
+      setOutputFormat('tar');
+      setAssetRules([]);
+      setOptimizePng(false);
+      setAssetFiles([]);
+      setCoverage(null);
+      setFilesMissing(null);
+    } catch (err) {
+      console.error(err);
+    }
+  }, []);
+
+  const handleClearCache = useCallback(async () => {
+    try {
+      await invoke('clear_cache');
+    } catch (err) {
+      console.error(err);
+    }
+  }, []);
+
+  const handleMoveFileUp = useCallback(
+    (fileName: string) => async () => {
+      try {
+        const files = await invoke<string[]>('move_file_up', { fileName });
+        setAssetFiles(files);
+        setCoverage(await invoke<Coverage>('get_coverage'));
+      } catch (err) {
+        console.error(err);
+      }
+    },
+    []
+  );
+
+  const handleDiffMods = useCallback(async () => {
+    try {
+      setLoading(true);
+      log.current = [];
+      setLogEntries([]);
+      setModDiffs(await invoke<ModDiff[]>('diff_mods'));
+    } catch (err) {
+      console.error(err);
+    }
+    setLoading(false);
+  }, []);
+
+  const handleSetOutputFormat = useCallback(
+    async (outputFormat: OutputFormat) => {
+      try {
+        await invoke('set_output_format', { outputFormat });
+        setOutputFormat(outputFormat);
+      } catch (err) {
+        console.error(err);
+      }
+    },
+    []
+  );
+
+  const handleSetAssetRules = useCallback(async (assetRules: AssetRule[]) => {
+    try {
+      await invoke('set_asset_rules', { assetRules });
+      setAssetRules(assetRules);
+    } catch (err) {
+      console.error(err);
+    }
+  }, []);
+
+  const handleSetOptimizePng = useCallback(async (optimizePng: boolean) => {
+    try {
+      await invoke('set_optimize_png', { optimizePng });
+      setOptimizePng(optimizePng);
+    } catch (err) {
+      console.error(err);
+    }
+  }, []);
+
+  const handleSetLogDetails = useCallback(async (details: boolean) => {
+    try {
+      const entries = await invoke<LogEntry[]>('set_log_details', { details });
+      log.current = entries;
+      setLogEntries([...entries]);
+      setLogDetails(details);
+    } catch (err) {
+      console.error(err);
+    }
+  }, []);
+
+  const handleExportLog = useCallback(async () => {
+    try {
+      await invoke('export_log');
+    } catch (err) {
+      console.error(err);
+    }
+  }, []);
+
   const handleStart = useCallback(async () => {
     try {
       setLoading(true);
       setExtractProgress(0);
+      setProgressEvent(null);
       setExtractError(null);
-      setExtractMessages('');
+      log.current = [];
+      setLogEntries([]);
       setExtractStep('');
-      await invoke('extract_assets');
+      setConflicts([]);
+      setConflicts(await invoke<Conflict[]>('extract_assets'));
       setBundleData(true);
     } catch (err) {
       setExtractProgress(0);
-      setExtractError(err as unknown as Record<string, string>);
+      setExtractError(err as unknown as AppError);
+      console.error(err);
+    }
+    // game dumps are only listed once they have been extracted
+    try {
+      setCoverage(await invoke<Coverage>('get_coverage'));
+    } catch (err) {
       console.error(err);
     }
     setLoading(false);
@@ -165,11 +438,25 @@ export const App: FC = () => {
               keys={keys}
               prodKey={prodKey}
               assetFiles={assetFiles}
+              coverage={coverage}
+              baseBundle={baseBundle}
+              baseBundleError={baseBundleError}
               filesMissing={filesMissing}
               handleSetProdKey={handleSetProdKey}
               handleSelectProdKey={handleSelectProdKey}
               handleAddFiles={handleAddFiles}
               handleRemoveFile={handleRemoveFile}
+              handleLoadBaseBundle={handleLoadBaseBundle}
+              handleRemoveBaseBundle={handleRemoveBaseBundle}
+              handleMoveFileUp={handleMoveFileUp}
+              handleResetSettings={handleResetSettings}
+              handleClearCache={handleClearCache}
+              modDiffs={modDiffs}
+              handleDiffMods={handleDiffMods}
+              assetRules={assetRules}
+              handleSetAssetRules={handleSetAssetRules}
+              optimizePng={optimizePng}
+              handleSetOptimizePng={handleSetOptimizePng}
             />
           ),
           onNext: handleStart,
@@ -178,7 +465,7 @@ export const App: FC = () => {
           nextDisabled:
             assetFiles.length === 0 ||
             (!!assetFiles.find(
-              file => file.endsWith('.xci') || file.endsWith('.nsp')
+              file => /\.(xci|xcz|nsp|nsz)$/i.test(file)
             ) &&
               prodKey == null)
         },
@@ -186,9 +473,16 @@ export const App: FC = () => {
           component: (
             <ExtractProgress
               extractStep={extractStep}
-              extractMessages={extractMessages}
+              logEntries={logEntries}
+              logDetails={logDetails}
+              handleSetLogDetails={handleSetLogDetails}
+              handleExportLog={handleExportLog}
               extractProgress={extractProgress}
+              progressEvent={progressEvent}
               extractError={extractError}
+              conflicts={conflicts}
+              outputFormat={outputFormat}
+              handleSetOutputFormat={handleSetOutputFormat}
             />
           ),
 
@@ -0,0 +1,118 @@
+import React, { FC, useEffect, useState } from 'react';
+
+import * as Icon from '@geist-ui/icons';
+import { Button, Checkbox, Grid, Input, Select, Text } from '@geist-ui/core';
+
+import { AssetRule, ImageEncoding } from './app';
+
+export const AssetRules: FC<{
+  loading: boolean;
+  assetRules: AssetRule[];
+  handleSetAssetRules: (assetRules: AssetRule[]) => Promise<void>;
+  optimizePng: boolean;
+  handleSetOptimizePng: (optimizePng: boolean) => Promise<void>;
+}> = ({
+  loading,
+  assetRules,
+  handleSetAssetRules,
+  optimizePng,
+  handleSetOptimizePng
+}) => {
+  // patterns are only applied on blur, so that incomplete patterns can be typed
+  const [patterns, setPatterns] = useState<string[]>([]);
+
+  useEffect(() => {
+    setPatterns(assetRules.map(({ pattern }) => pattern));
+  }, [assetRules]);
+
+  const updateRule = (index: number, rule: Partial<AssetRule>) =>
+    handleSetAssetRules(
+      assetRules.map((assetRule, i) =>
+        i === index ? { ...assetRule, ...rule } : assetRule
+      )
+    );
+
+  return (
+    <div style={{ maxWidth: '36rem', minWidth: '24rem', margin: '1rem 0' }}>
+      <Text small>
+        Images are saved as PNG by default. You can select another format for
+        all images matching a pattern, e.g. &quot;*_Field_*.png&quot; for
+        tiles. The first matching rule wins.
+      </Text>
+      <Grid.Container>
+        {assetRules.map(({ encoding }, index) => (
+          <Grid
+            key={index}
+            xs={24}
+            style={{ marginBottom: '0.6rem', alignItems: 'center' }}
+          >
+            <Input
+              scale={0.5}
+              disabled={loading}
+              value={patterns[index] ?? ''}
+              onChange={event =>
+                setPatterns(
+                  patterns.map((pattern, i) =>
+                    i === index ? event.target.value : pattern
+                  )
+                )
+              }
+              onBlur={() => updateRule(index, { pattern: patterns[index] })}
+              style={{ flex: '1 1 auto' }}
+            />
+            <Select
+              scale={0.5}
+              disabled={loading}
+              value={encoding}
+              onChange={value =>
+                updateRule(index, { encoding: value as ImageEncoding })
+              }
+              style={{ margin: '0 0.4rem' }}
+            >
+              <Select.Option value="png">PNG</Select.Option>
+              <Select.Option value="webp">WebP (lossless)</Select.Option>
+              <Select.Option value="avif">AVIF (lossy)</Select.Option>
+              <Select.Option value="ktx2_rgba8">
+                KTX2 RGBA8 + Zstandard (GPU upload, not Basis)
+              </Select.Option>
+            </Select>
+            <Button
+              auto
+              scale={0.5}
+              type="error"
+              disabled={loading}
+              icon={<Icon.Trash2 />}
+              onClick={() =>
+                handleSetAssetRules(assetRules.filter((_, i) => i !== index))
+              }
+            />
+          </Grid>
+        ))}
+      </Grid.Container>
+      <Button
+        auto
+        scale={0.5}
+        type="secondary-light"
+        disabled={loading}
+        iconRight={<Icon.Image />}
+        onClick={() =>
+          handleSetAssetRules([
+            ...assetRules,
+            { pattern: '*_Field_*.png', encoding: 'webp' }
+          ])
+        }
+      >
+        Add image format rule
+      </Button>
+      <div style={{ marginTop: '0.6rem' }}>
+        <Checkbox
+          checked={optimizePng}
+          disabled={loading}
+          onChange={event => handleSetOptimizePng(event.target.checked)}
+        >
+          Optimize PNG images further (slow)
+        </Checkbox>
+      </div>
+    </div>
+  );
+};
@@ -0,0 +1,83 @@
+import React, { FC } from 'react';
+
+import { Button, Checkbox, Text } from '@geist-ui/core';
+import * as Icon from '@geist-ui/icons';
+
+export type LogLevel = 'debug' | 'info' | 'warn' | 'error';
+
+export interface LogEntry {
+  level: LogLevel;
+  phase: string | null;
+  input: string | null;
+  message: string;
+  timestamp: number;
+}
+
+const LEVEL_COLORS: Record<LogLevel, string> = {
+  debug: '#999',
+  info: 'inherit',
+  warn: '#f5a623',
+  error: '#e00'
+};
+
+const fileName = (path: string): string => path.split(/[\\/]/).pop() ?? path;
+
+const formatTime = (timestamp: number): string =>
+  new Date(timestamp).toLocaleTimeString();
+
+export const LogView: FC<{
+  logEntries: LogEntry[];
+  logDetails: boolean;
+  handleSetLogDetails: (details: boolean) => Promise<void>;
+  handleExportLog: () => Promise<void>;
+}> = ({
+  logEntries,
+  logDetails,
+  handleSetLogDetails,
+  handleExportLog
+}) => {
+  // debug entries are only streamed while details are shown
+  const entries = logDetails
+    ? logEntries
+    : logEntries.filter(({ level }) => level !== 'debug');
+
+  return (
+    <>
+      <div style={{ display: 'flex', alignItems: 'center', gap: '1rem' }}>
+        <Checkbox
+          checked={logDetails}
+          onChange={event => handleSetLogDetails(event.target.checked)}
+        >
+          Show details
+        </Checkbox>
+        <Button
+          auto
+          scale={0.5}
+          icon={<Icon.Download />}
+          disabled={logEntries.length === 0}
+          onClick={handleExportLog}
+        >
+          Export log
+        </Button>
+      </div>
+      <pre
+        style={{
+          maxWidth: '100%',
+          minWidth: '100%',
+          maxHeight: '16rem',
+          overflowY: 'auto',
+          whiteSpace: 'break-spaces',
+          wordBreak: 'break-word'
+        }}
+      >
+        {entries.length === 0 && <Text small>No log entries</Text>}
+        {entries.map((entry, i) => (
+          <div key={i} style={{ color: LEVEL_COLORS[entry.level] }}>
+            [{formatTime(entry.timestamp)}] {entry.level.toUpperCase()}
+            {entry.input && ` ${fileName(entry.input)}`}: {entry.message}
+          </div>
+        ))}
+      </pre>
+    </>
+  );
+};
@@ -0,0 +1,64 @@
+import React, { FC } from 'react';
+
+import { Table, Text } from '@geist-ui/core';
+
+export interface PixelDiff {
+  size_changed: boolean;
+  changed_pixels: number;
+  total_pixels: number;
+  max_delta: number;
+  mean_delta: number;
+}
+
+export interface TextureDiff {
+  asset: string;
+  texture: string;
+  status: 'added' | 'removed' | 'changed';
+  pixel_diff?: PixelDiff;
+  thumbnail?: string;
+}
+
+export interface ModDiff {
+  input: string;
+  textures: TextureDiff[];
+}
+
+const formatPixelDiff = (pixelDiff?: PixelDiff): string => {
+  if (!pixelDiff) return '-';
+  if (pixelDiff.size_changed) return 'size changed';
+  const percentage = (pixelDiff.changed_pixels / pixelDiff.total_pixels) * 100;
+  return `${percentage.toFixed(1)}% pixels, max Δ ${
+    pixelDiff.max_delta
+  }, mean Δ ${pixelDiff.mean_delta.toFixed(2)}`;
+};
+
+export const ModDiffReport: FC<{ modDiffs: ModDiff[] }> = ({ modDiffs }) => (
+  <>
+    {modDiffs.map(({ input, textures }) => (
+      <div key={input} style={{ maxWidth: '36rem', margin: '0.6rem 0' }}>
+        <Text h5 style={{ wordBreak: 'break-word' }}>
+          {input}
+        </Text>
+        {textures.length === 0 ? (
+          <Text small>No textures changed</Text>
+        ) : (
+          <Table
+            data={textures.map(texture => ({
+              thumbnail: texture.thumbnail ? (
+                <img src={texture.thumbnail} alt={texture.texture} />
+              ) : null,
+              texture: texture.texture,
+              status: texture.status,
+              difference: formatPixelDiff(texture.pixel_diff)
+            }))}
+          >
+            <Table.Column prop="thumbnail" label="" />
+            <Table.Column prop="texture" label="Texture" />
+            <Table.Column prop="status" label="Status" />
+            <Table.Column prop="difference" label="Difference" />
+          </Table>
+        )}
+      </div>
+    ))}
+  </>
+);
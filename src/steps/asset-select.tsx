@@ -3,27 +3,74 @@ import React, { FC } from 'react';
 import * as Icon from '@geist-ui/icons';
 import { Button, Grid, Text } from '@geist-ui/core';
 
+import { AssetRule, Coverage, FoundKey } from '../app';
+import { AssetRules } from '../asset-rules';
+import { ModDiff, ModDiffReport } from '../mod-diff-report';
+
 export const AssetSelect: FC<{
   loading: boolean;
-  keys: string[];
+  keys: FoundKey[];
   prodKey: string | null;
   assetFiles: string[];
+  coverage: Coverage | null;
+  baseBundle: string | null;
+  baseBundleError: string | null;
   filesMissing: string[] | null;
   handleSetProdKey: (prodKey: string) => () => Promise<void>;
   handleSelectProdKey: () => Promise<void>;
-  handleAddFiles: () => Promise<void>;
+  handleAddFiles: (directory: boolean) => () => Promise<void>;
   handleRemoveFile: (fileName: string) => () => Promise<void>;
+  handleLoadBaseBundle: () => Promise<void>;
+  handleRemoveBaseBundle: () => Promise<void>;
+  handleMoveFileUp: (fileName: string) => () => Promise<void>;
+  handleResetSettings: () => Promise<void>;
+  handleClearCache: () => Promise<void>;
+  modDiffs: ModDiff[];
+  handleDiffMods: () => Promise<void>;
+  assetRules: AssetRule[];
+  handleSetAssetRules: (assetRules: AssetRule[]) => Promise<void>;
+  optimizePng: boolean;
+  handleSetOptimizePng: (optimizePng: boolean) => Promise<void>;
 }> = ({
   loading,
   keys,
   prodKey,
   assetFiles,
+  coverage,
+  baseBundle,
+  baseBundleError,
   filesMissing,
   handleSetProdKey,
   handleSelectProdKey,
   handleAddFiles,
-  handleRemoveFile
+  handleRemoveFile,
+  handleLoadBaseBundle,
+  handleRemoveBaseBundle,
+  handleMoveFileUp,
+  handleResetSettings,
+  handleClearCache,
+  modDiffs,
+  handleDiffMods,
+  assetRules,
+  handleSetAssetRules,
+  optimizePng,
+  handleSetOptimizePng
 }) => {
+  const requiredFileCount = coverage
+    ? Object.keys(coverage.suppliers).length
+    : 0;
+  const coverageLabel = (assetFile: string) => {
+    const inputCoverage = coverage?.inputs.find(
+      ({ input }) => input === assetFile
+    );
+    if (!inputCoverage) return null;
+    if (!inputCoverage.listed) return 'Content known after extraction';
+    const supplied = Object.values(coverage?.suppliers ?? {}).filter(
+      supplier => supplier === assetFile
+    ).length;
+    return `${inputCoverage.file_count} files, contains ${inputCoverage.required_files.length}/${requiredFileCount} required files, supplies ${supplied}`;
+  };
+
   return (
     <>
       <Text>
@@ -38,7 +85,7 @@ export const AssetSelect: FC<{
         }}
       >
         <Grid.Container>
-          {keys.map(key => (
+          {keys.map(({ path: key, source }) => (
             <Grid
               key={key}
               xs={24}
@@ -51,7 +98,10 @@ export const AssetSelect: FC<{
                   wordBreak: 'break-word'
                 }}
               >
-                {key}
+                <div>{key}</div>
+                <div style={{ fontSize: '0.8rem', color: 'gray' }}>
+                  {source}
+                </div>
               </div>
               <Button
                 auto
@@ -82,8 +132,61 @@ export const AssetSelect: FC<{
       <Text style={{ marginTop: '2rem' }}>
         Please select all your game resource files from Super Mario Maker 2:
       </Text>
+      <Text small>
+        Your game dump is always applied first. Mods are applied in the listed
+        order, so a mod overrides the assets of all mods above it.
+      </Text>
+      <Text small>
+        If you only want to apply a partial mod, you can load a bundle you
+        saved before as tar, tar.gz or tar.zst instead of selecting your game
+        files again. Only assets, which have changed since then, will be
+        processed again.
+      </Text>
+      <div style={{ maxWidth: '36rem', minWidth: '24rem' }}>
+        <Grid.Container style={{ marginBottom: '0.6rem' }}>
+          <Grid xs={24} style={{ alignItems: 'center' }}>
+            <span
+              style={{
+                flex: '1 1 auto',
+                marginRight: '1rem',
+                wordBreak: 'break-word'
+              }}
+            >
+              Base bundle: {baseBundle ?? '-'}
+            </span>
+            {baseBundle ? (
+              <Button
+                auto
+                scale={0.5}
+                type="error"
+                disabled={loading}
+                icon={<Icon.Trash2 />}
+                onClick={handleRemoveBaseBundle}
+              />
+            ) : (
+              <Button
+                auto
+                scale={0.5}
+                type="secondary-light"
+                disabled={loading}
+                iconRight={<Icon.Package />}
+                onClick={handleLoadBaseBundle}
+              >
+                Load bundle
+              </Button>
+            )}
+          </Grid>
+          {baseBundleError && (
+            <Grid xs={24}>
+              <Text small type="error">
+                {baseBundleError}
+              </Text>
+            </Grid>
+          )}
+        </Grid.Container>
+      </div>
       <Grid.Container style={{ maxWidth: '36rem', minWidth: '24rem' }}>
-        {assetFiles.map(assetFile => (
+        {assetFiles.map((assetFile, i) => (
           <Grid key={assetFile} xs={24} style={{ marginBottom: '0.6rem' }}>
             <span
               style={{
@@ -92,8 +195,20 @@ export const AssetSelect: FC<{
                 wordBreak: 'break-word'
               }}
             >
-              {assetFile}
+              <div>{assetFile}</div>
+              <div style={{ fontSize: '0.8rem', color: 'gray' }}>
+                {coverageLabel(assetFile)}
+              </div>
             </span>
+            <Button
+              auto
+              scale={0.5}
+              type="secondary-light"
+              disabled={loading || i === 0}
+              icon={<Icon.ArrowUp />}
+              onClick={handleMoveFileUp(assetFile)}
+              style={{ marginRight: '0.4rem' }}
+            />
             <Button
               auto
               scale={0.5}
@@ -149,14 +264,60 @@ export const AssetSelect: FC<{
         </div>
       )}
 
+      <AssetRules
+        loading={loading}
+        assetRules={assetRules}
+        handleSetAssetRules={handleSetAssetRules}
+        optimizePng={optimizePng}
+        handleSetOptimizePng={handleSetOptimizePng}
+      />
+
       <Button
         type="success-light"
         disabled={loading}
         iconRight={<Icon.PlusCircle />}
-        onClick={handleAddFiles}
+        onClick={handleAddFiles(false)}
       >
         Add
       </Button>
+      <Button
+        type="success-light"
+        disabled={loading}
+        iconRight={<Icon.FolderPlus />}
+        onClick={handleAddFiles(true)}
+        style={{ marginLeft: '0.6rem' }}
+      >
+        Add folder
+      </Button>
+      <Button
+        type="secondary-light"
+        disabled={loading || assetFiles.length < 2}
+        iconRight={<Icon.GitPullRequest />}
+        onClick={handleDiffMods}
+        style={{ marginLeft: '0.6rem' }}
+      >
+        Compare mods
+      </Button>
+      <Button
+        type="abort"
+        disabled={loading}
+        iconRight={<Icon.RotateCcw />}
+        onClick={handleResetSettings}
+        style={{ marginLeft: '0.6rem' }}
+      >
+        Reset
+      </Button>
+      <Button
+        type="abort"
+        disabled={loading}
+        iconRight={<Icon.Trash2 />}
+        onClick={handleClearCache}
+        style={{ marginLeft: '0.6rem' }}
+      >
+        Clear cache
+      </Button>
+
+      <ModDiffReport modDiffs={modDiffs} />
     </>
   );
 };
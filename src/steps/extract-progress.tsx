@@ -1,39 +1,126 @@
 import React, { FC } from 'react';
 
-import { Note, Progress, Text } from '@geist-ui/core';
+import { Note, Progress, Select, Text } from '@geist-ui/core';
+
+import {
+  AppError,
+  Conflict,
+  OutputFormat,
+  PhaseProgress,
+  ProgressEvent
+} from '../app';
+import { LogEntry, LogView } from '../log-view';
+
+const PHASE_LABELS: Record<string, string> = {
+  decrypt: 'Decrypting',
+  bundle: 'Bundling',
+  encode: 'Encoding',
+  compare: 'Comparing',
+  finish: 'Finishing'
+};
+
+const formatDuration = (secs: number): string => {
+  const rounded = Math.round(secs);
+  const minutes = Math.floor(rounded / 60);
+  const seconds = rounded % 60;
+  return minutes > 0 ? `${minutes}m ${seconds}s` : `${seconds}s`;
+};
+
+const formatThroughput = ({ phase, throughput }: PhaseProgress): string => {
+  if (throughput == null) {
+    return '';
+  }
+  switch (phase) {
+    case 'decrypt':
+      return `${(throughput / 1024 / 1024).toFixed(1)} MB/s`;
+    case 'bundle':
+      return `${throughput.toFixed(1)} files/s`;
+    case 'encode':
+      return `${throughput.toFixed(1)} images/s`;
+    case 'compare':
+      return `${throughput.toFixed(1)} mods/s`;
+    default:
+      return '';
+  }
+};
 
 export const ExtractProgress: FC<{
   extractProgress: number;
-  extractMessages: string;
+  progressEvent: ProgressEvent | null;
+  logEntries: LogEntry[];
+  logDetails: boolean;
+  handleSetLogDetails: (details: boolean) => Promise<void>;
+  handleExportLog: () => Promise<void>;
   extractStep: string;
-  extractError: Record<string, string> | null;
-}> = ({ extractProgress, extractMessages, extractStep, extractError }) => (
+  extractError: AppError | null;
+  conflicts: Conflict[];
+  outputFormat: OutputFormat;
+  handleSetOutputFormat: (outputFormat: OutputFormat) => Promise<void>;
+}> = ({
+  extractProgress,
+  progressEvent,
+  logEntries,
+  logDetails,
+  handleSetLogDetails,
+  handleExportLog,
+  extractStep,
+  extractError,
+  conflicts,
+  outputFormat,
+  handleSetOutputFormat
+}) => (
   <>
     <div style={{ width: '100%', minHeight: '0.625rem' }}>
       <Progress value={extractProgress} type="success" />
     </div>
 
+    {progressEvent && extractProgress < 99.9 && (
+      <Text small type="secondary">
+        {PHASE_LABELS[progressEvent.phase]}
+        {progressEvent.phases
+          .filter(phase => phase.phase === progressEvent.phase)
+          .map(phase => {
+            const throughput = formatThroughput(phase);
+            return throughput ? ` at ${throughput}` : '';
+          })}
+        {progressEvent.eta_secs != null &&
+          `, about ${formatDuration(progressEvent.eta_secs)} remaining`}
+      </Text>
+    )}
+
     <div style={{ minHeight: '6rem', maxHeight: '6rem' }}>
       {extractStep.split('\n').map((step, i) => (
         <Text key={i}>{step}</Text>
       ))}
     </div>
 
-    <pre
-      style={{
-        maxWidth: '100%',
-        minWidth: '100%',
-        whiteSpace: 'break-spaces',
-        wordBreak: 'break-word'
-      }}
-    >
-      {extractMessages}
-    </pre>
+    <LogView
+      logEntries={logEntries}
+      logDetails={logDetails}
+      handleSetLogDetails={handleSetLogDetails}
+      handleExportLog={handleExportLog}
+    />
     {extractProgress >= 99.9 && (
       <>
         <Text>
-          Please save your file with whatever name you like and with a
-          &apos;.tar&apos; extension. You can now go back to{' '}
+          Please save your file with whatever name you like. The extension will
+          be set according to the selected format:
+        </Text>
+        <Select
+          value={outputFormat}
+          onChange={value => handleSetOutputFormat(value as OutputFormat)}
+        >
+          <Select.Option value="tar">.tar</Select.Option>
+          <Select.Option value="tar_gz">.tar.gz</Select.Option>
+          <Select.Option value="tar_zst">.tar.zst</Select.Option>
+          <Select.Option value="zip">.zip</Select.Option>
+          <Select.Option value="directory">Folder</Select.Option>
+          <Select.Option value="web">
+            Folder with hashed file names and index.json
+          </Select.Option>
+        </Select>
+        <Text>
+          You can now go back to{' '}
           <a
             href="https://app.shroomkingdom.net/"
             target="_blank"
@@ -49,11 +136,21 @@ export const ExtractProgress: FC<{
         </Text>
       </>
     )}
+    {conflicts.length > 0 && (
+      <Note label="Overridden assets" type="warning">
+        {conflicts.map(({ asset, overridden, by }) => (
+          <div
+            key={`${asset}${overridden}`}
+            style={{ wordBreak: 'break-word' }}
+          >
+            {asset}: {overridden} &rarr; {by}
+          </div>
+        ))}
+      </Note>
+    )}
     {extractError && (
       <Note label="Error" type="error">
-        {Object.entries(extractError).map(
-          ([key, value]) => `[${key}]: ${value}`
-        )}
+        {extractError.message}
       </Note>
     )}
   </>
@@ -41,8 +41,9 @@ export const Intro: FC = () => {
         our preset: [WIP]
       </Text>
       <Text>
-        This software is in early access. You can currently only extract XCI,
-        ZIP and 7z files. Support for NSP files will be added later.
+        This software is in early access. You can extract XCI and NSP files
+        (also compressed as XCZ and NSZ), mod archives (ZIP, 7z, RAR, tar) and
+        folders.
       </Text>
     </>
   );